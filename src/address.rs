@@ -0,0 +1,271 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of polkadot-rewards.
+
+// polkadot-rewards is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// polkadot-rewards is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with polkadot-rewards.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Validation and encoding of SS58 and H160 account addresses.
+
+use crate::network::{Network, Registry};
+use anyhow::{anyhow, bail, ensure, Error};
+use blake2::{Blake2b512, Digest};
+use itertools::Itertools;
+use std::convert::TryInto;
+
+/// Prepended to the address before hashing it into the checksum.
+const SS58_HASH_PREFIX: &[u8] = b"SS58PRE";
+/// Prefix of addresses which are not specific to any network.
+const GENERIC_PREFIX: u16 = 42;
+
+/// A decoded account address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Address {
+	/// A Substrate account, with the prefix of the network it was encoded for.
+	Ss58 { prefix: u16, account: Vec<u8> },
+	/// A hex-encoded 32-byte public key, valid on every Substrate network.
+	PublicKey([u8; 32]),
+	/// A 20-byte Ethereum-style account, as used on Moonbeam and Moonriver.
+	H160([u8; 20]),
+}
+
+impl Address {
+	/// Decode an SS58 address and verify its checksum, or parse a `0x`-prefixed public key or H160 address.
+	pub fn parse(address: &str) -> Result<Self, Error> {
+		if let Some(hex) = address.strip_prefix("0x") {
+			let bytes = hex::decode(hex).map_err(|e| anyhow!("Invalid address '{address}': {e}"))?;
+			return match bytes.len() {
+				20 => Ok(Address::H160(bytes.try_into().expect("length checked"))),
+				32 => Ok(Address::PublicKey(bytes.try_into().expect("length checked"))),
+				_ => bail!("Invalid address '{address}': expected a 32-byte public key or a 20-byte H160 address"),
+			};
+		}
+
+		let data = bs58::decode(address).into_vec().map_err(|e| anyhow!("Invalid address '{address}': {e}"))?;
+		let (prefix, prefix_len) = match data.first() {
+			Some(0..=63) => (data[0] as u16, 1),
+			Some(64..=127) if data.len() > 1 => {
+				// two byte prefixes are stored with the 6 low bits of the first byte shifted in front of the second
+				let lower = (data[0] << 2) | (data[1] >> 6);
+				let upper = data[1] & 0b0011_1111;
+				(lower as u16 | (upper as u16) << 8, 2)
+			}
+			_ => bail!("Invalid address '{address}': unknown SS58 prefix"),
+		};
+		let (account_len, checksum_len) = match data.len() - prefix_len {
+			2 => (1, 1),
+			3 => (2, 1),
+			5 => (4, 1),
+			9 => (8, 1),
+			34 => (32, 2),
+			35 => (33, 2),
+			_ => bail!("Invalid address '{address}': unexpected length"),
+		};
+		let (payload, checksum) = data.split_at(prefix_len + account_len);
+		ensure!(
+			checksum == &ss58_hash(payload)[..checksum_len],
+			"Invalid address '{address}': checksum mismatch. Is there a typo?"
+		);
+		Ok(Address::Ss58 { prefix, account: payload[prefix_len..].to_vec() })
+	}
+
+	/// Whether this kind of address can be used on `network`.
+	fn fits(&self, network: &Network) -> bool {
+		matches!(self, Address::H160(_)) == network.h160
+	}
+
+	/// Encode the address the way `network` formats its addresses.
+	pub fn encode(&self, network: &Network) -> String {
+		match self {
+			Address::H160(account) => format!("0x{}", hex::encode(account)),
+			Address::PublicKey(account) => ss58_encode(network.ss58_prefix, account),
+			Address::Ss58 { account, .. } => ss58_encode(network.ss58_prefix, account),
+		}
+	}
+}
+
+/// Encode an account with the SS58 `prefix` of a network.
+fn ss58_encode(prefix: u16, account: &[u8]) -> String {
+	let mut data = match prefix {
+		0..=63 => vec![prefix as u8],
+		_ => {
+			vec![((prefix & 0b1111_1100) as u8 >> 2) | 0b0100_0000, (prefix >> 8) as u8 | ((prefix & 0b11) as u8) << 6]
+		}
+	};
+	data.extend(account);
+	let checksum_len = if account.len() >= 32 { 2 } else { 1 };
+	let checksum = ss58_hash(&data);
+	data.extend(&checksum[..checksum_len]);
+	bs58::encode(data).into_string()
+}
+
+fn ss58_hash(data: &[u8]) -> Vec<u8> {
+	let mut hasher = Blake2b512::new();
+	hasher.update(SS58_HASH_PREFIX);
+	hasher.update(data);
+	hasher.finalize().to_vec()
+}
+
+/// The network of `address`, and the address encoded for it.
+///
+/// The network is the one named `network`, or the one inferred from the address if `None`. Public keys and
+/// generic-prefix addresses are encoded for the network. Warns if the address was encoded for another network.
+pub fn resolve<'a>(
+	registry: &'a Registry,
+	network: Option<&str>,
+	address: &str,
+) -> Result<(&'a Network, String), Error> {
+	let decoded = Address::parse(address)?;
+	let network = match network {
+		Some(name) => {
+			let network = registry.get(name)?;
+			match &decoded {
+				Address::H160(_) if !network.h160 => {
+					bail!("{address} is an H160 address, but {} uses SS58 addresses", network.id())
+				}
+				_ if network.h160 && !decoded.fits(network) => {
+					bail!("{} uses H160 addresses (0x...), but {address} is not one", network.id())
+				}
+				Address::Ss58 { prefix, .. } if *prefix != network.ss58_prefix => {
+					// the generic prefix fits any network, unless a network uses it as its own
+					let owners = registry.iter().filter(|n| n.ss58_prefix == *prefix).map(|n| n.id()).join(", ");
+					if *prefix != GENERIC_PREFIX || !owners.is_empty() {
+						log::warn!(
+							"{address} is encoded with SS58 prefix {prefix}{}, but {} uses prefix {}",
+							if owners.is_empty() { String::new() } else { format!(" of {owners}") },
+							network.id(),
+							network.ss58_prefix
+						)
+					}
+				}
+				_ => {}
+			}
+			network
+		}
+		None => infer(registry, &decoded, address)?,
+	};
+	Ok((network, decoded.encode(network)))
+}
+
+/// The only network `address` may belong to.
+fn infer<'a>(registry: &'a Registry, decoded: &Address, address: &str) -> Result<&'a Network, Error> {
+	let candidates = registry
+		.iter()
+		.filter(|n| decoded.fits(n))
+		.filter(|n| match decoded {
+			// generic addresses are no hint at the network, even if a network uses the generic prefix
+			Address::Ss58 { prefix, .. } if *prefix != GENERIC_PREFIX => n.ss58_prefix == *prefix,
+			Address::Ss58 { .. } | Address::PublicKey(_) | Address::H160(_) => true,
+		})
+		.collect::<Vec<_>>();
+	match candidates.as_slice() {
+		[network] => {
+			log::info!("Inferred network {} from address {address}", network.id());
+			Ok(network)
+		}
+		[] => bail!("No known network uses the prefix of {address}. Select one with `--network`."),
+		_ => bail!(
+			"{address} may belong to any of {}. Select one with `--network`.",
+			candidates.iter().map(|n| n.id()).join(", ")
+		),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Public key of the well-known `//Alice` development account.
+	const ALICE: &str = "0xd43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d";
+	/// `ALICE` encoded with SS58 prefixes 0 (Polkadot), 2 (Kusama), 42 (generic) and 78 (Calamari).
+	const VECTORS: [(u16, &str); 4] = [
+		(0, "15oF4uVJwmo4TdGW7VfQxNLavjCXviqxT9S1MgbjMNHr6Sp5"),
+		(2, "HNZata7iMYWmk5RvZRTiAsSDhV8366zq2YGb3tLH5Upf74F"),
+		(42, "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY"),
+		(78, "dmyjURuBeJwFo4Nvf2GZ8f5E2Asz98JY2d7UcaDykqYm1zpoi"),
+	];
+
+	fn alice() -> Vec<u8> {
+		hex::decode(&ALICE[2..]).unwrap()
+	}
+
+	fn network(prefix: u16) -> Network {
+		Registry::builtin().iter().find(|n| n.ss58_prefix == prefix).unwrap().clone()
+	}
+
+	#[test]
+	fn ss58_addresses_are_decoded() {
+		for (prefix, address) in VECTORS {
+			assert_eq!(Address::parse(address).unwrap(), Address::Ss58 { prefix, account: alice() }, "{}", address);
+		}
+	}
+
+	#[test]
+	fn ss58_addresses_are_encoded() {
+		let public_key = Address::parse(ALICE).unwrap();
+		assert_eq!(public_key, Address::PublicKey(alice().try_into().unwrap()));
+		for (prefix, address) in VECTORS {
+			assert_eq!(public_key.encode(&network(prefix)), address);
+			// re-encoding an address for another network keeps the account
+			assert_eq!(Address::parse(VECTORS[0].1).unwrap().encode(&network(prefix)), address);
+		}
+	}
+
+	#[test]
+	fn flipped_checksums_are_rejected() {
+		for (_, address) in VECTORS {
+			let mut flipped = address.to_string();
+			let last = flipped.pop().unwrap();
+			flipped.push(if last == '1' { '2' } else { '1' });
+			let error = Address::parse(&flipped).unwrap_err().to_string();
+			assert!(error.contains("checksum mismatch"), "{}: {}", flipped, error);
+		}
+	}
+
+	#[test]
+	fn malformed_addresses_are_rejected() {
+		assert!(Address::parse("").is_err());
+		// `0` is not part of the base58 alphabet
+		assert!(Address::parse("0GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY").is_err());
+		// one character short
+		assert!(Address::parse(&VECTORS[0].1[1..]).is_err());
+		assert!(Address::parse("0x1234").is_err());
+		assert!(Address::parse("0xzz").is_err());
+	}
+
+	#[test]
+	fn generic_addresses_need_a_network() {
+		let registry = Registry::builtin();
+		let error = resolve(&registry, None, VECTORS[2].1).unwrap_err().to_string();
+		assert!(error.contains("Select one with `--network`"), "{}", error);
+		let (network, address) = resolve(&registry, Some("aleph"), VECTORS[2].1).unwrap();
+		assert_eq!((network.id(), address.as_str()), ("aleph", VECTORS[2].1));
+		let (network, address) = resolve(&registry, Some("polkadot"), VECTORS[2].1).unwrap();
+		assert_eq!((network.id(), address.as_str()), ("polkadot", VECTORS[0].1));
+	}
+
+	#[test]
+	fn networks_are_inferred_from_their_prefix() {
+		let registry = Registry::builtin();
+		for (prefix, address) in [VECTORS[0], VECTORS[1], VECTORS[3]] {
+			assert_eq!(resolve(&registry, None, address).unwrap(), (&network(prefix), address.to_string()));
+		}
+		assert!(resolve(&registry, None, ALICE).is_err());
+	}
+
+	#[test]
+	fn h160_addresses_are_lowercased() {
+		let address = Address::parse("0xF24FF3a9CF04c71Dbc94D0b566f7A27B94566cac").unwrap();
+		assert!(matches!(address, Address::H160(_)));
+		assert_eq!(address.encode(&network(1284)), "0xf24ff3a9cf04c71dbc94d0b566f7a27b94566cac");
+	}
+}
@@ -17,165 +17,250 @@
 //! Wrapper around calls to Subscans API
 
 use crate::{
-	cli::{App, Network},
-	primitives::{ApiResponse, List, Price, Reward, RewardEntry, SeparatedRewardEntry},
+	address,
+	cache::{self, CacheStore, Cached},
+	err,
+	fixtures::Fixtures,
+	http::{Client, Rate, RetryPolicy},
+	network::{self, Network, Registry},
+	pool,
+	price::{self, CoinGecko, CryptoCompare, PriceProvider, PriceSource},
+	primitives::{Priced, Reward, RewardEntry, SeparatedRewardEntry},
+	source::{JsonFile, RewardSource, Source, Subscan, SUBSCAN_RPS, SUBSCAN_RPS_WITH_KEY},
 };
-use anyhow::{anyhow, Context, Error};
+use anyhow::{ensure, Context, Error};
 use chrono::{naive::NaiveDateTime, NaiveDate};
 use indicatif::ProgressBar;
-use kv::{Bucket, Config, Store};
+use itertools::Itertools;
+use kv::Bucket;
 use std::{
-	collections::{BTreeMap, BTreeSet},
+	collections::{BTreeMap, BTreeSet, HashMap},
 	convert::TryInto,
+	path::PathBuf,
+	sync::Mutex,
 };
 
-const POLKADOT_ENDPOINT: &str = "https://polkadot.api.subscan.io/api/";
-const KUSAMA_ENDPOINT: &str = "https://kusama.api.subscan.io/api/";
-const MOONRIVER_ENDPOINT: &str = "https://moonriver.api.subscan.io/api/";
-const MOONBEAM_ENDPOINT: &str = "https://moonbeam.api.subscan.io/api/";
-const ASTAR_ENDPOINT: &str = "https://astar.api.subscan.io/api/";
-const CALAMARI_ENDPOINT: &str = "https://calamari.api.subscan.io/api/";
-const ALEPH_ENDPOINT: &str = "https://alephzero.api.subscan.io/api/";
-
-const PRICE_ENDPOINT: &str = "https://api.coingecko.com/api/v3";
-const REWARD_SLASH: &str = "scan/account/reward_slash";
-
-fn get_endpoint(network: &Network, end: &str) -> String {
-	match network {
-		Network::Polkadot => format!("{POLKADOT_ENDPOINT}{end}"),
-		Network::Kusama => format!("{KUSAMA_ENDPOINT}{end}"),
-		Network::Moonriver => format!("{MOONRIVER_ENDPOINT}{end}"),
-		Network::Moonbeam => format!("{MOONBEAM_ENDPOINT}{end}"),
-		Network::Astar => format!("{ASTAR_ENDPOINT}{end}"),
-		Network::Calamari => format!("{CALAMARI_ENDPOINT}{end}"),
-		Network::Aleph => format!("{ALEPH_ENDPOINT}{end}"),
+/// Settings of the API, shared by all queries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiConfig {
+	/// user agent of HTTP requests
+	pub user_agent: String,
+	/// the subscan api key. Empty if there is none.
+	pub api_key: String,
+	/// directory of the local cache
+	pub cache_dir: PathBuf,
+	/// fiat currency of prices
+	pub currency: String,
+	pub source: Source,
+	pub price_source: PriceSource,
+	/// requests per second sent to Subscan. Depends on whether there is an API key by default.
+	pub subscan_rps: Option<f64>,
+	/// requests per minute sent to the price source. Depends on the price source by default.
+	pub price_rpm: Option<f64>,
+	/// how often to attempt a request which failed because of rate limits or server errors
+	pub max_attempts: u32,
+	/// how many requests to send at once, within the rate limits
+	pub jobs: usize,
+	/// record responses to, or replay them from, a directory of fixtures
+	pub fixtures: Option<Fixtures>,
+	/// base URL of the Subscan API of every network, instead of the one in the network registry. `{network}` is
+	/// replaced by the name of the network.
+	pub subscan_url: Option<String>,
+	/// base URL of the price source, instead of its public API
+	pub price_url: Option<String>,
+}
+
+impl Default for ApiConfig {
+	fn default() -> Self {
+		Self {
+			user_agent: format!("polkadot-rewards/{}", env!("CARGO_PKG_VERSION")),
+			api_key: String::new(),
+			cache_dir: cache::default_dir(),
+			currency: "usd".to_string(),
+			source: Source::Subscan,
+			price_source: PriceSource::CoinGecko,
+			subscan_rps: None,
+			price_rpm: None,
+			max_attempts: 5,
+			jobs: 4,
+			fixtures: None,
+			subscan_url: None,
+			price_url: None,
+		}
 	}
 }
 
-fn price_endpoint(network: &Network, day: NaiveDate) -> String {
-	format!("{}/coins/{}/history?date={}", PRICE_ENDPOINT, network.id(), day.format("%d-%m-%Y"),)
+/// The account, and the time range, to fetch rewards for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+	pub network: Network,
+	/// the address, encoded for `network`
+	pub address: String,
+	/// only fetch rewards after this date
+	pub from: Option<NaiveDateTime>,
+	/// only fetch rewards before this date
+	pub to: Option<NaiveDateTime>,
 }
 
-// TODO: Rate limit these requests so we don't end up trying to DoS subscan.
+impl Query {
+	pub fn new(network: Network, address: impl Into<String>) -> Self {
+		Self { network, address: address.into(), from: None, to: None }
+	}
+
+	/// Check `address` and look up its network in `registry`. The network is inferred from the address if `None`.
+	pub fn resolve(registry: &Registry, network: Option<&str>, address: &str) -> Result<Self, Error> {
+		let (network, address) = address::resolve(registry, network, address)?;
+		Ok(Self::new(network.clone(), address))
+	}
+
+	/// Only fetch rewards between `from` and `to`.
+	pub fn range(mut self, from: Option<NaiveDateTime>, to: Option<NaiveDateTime>) -> Self {
+		self.from = from;
+		self.to = to;
+		self
+	}
+}
 
 /// Wraps the subscan API to make things easy
-pub struct Api<'a> {
-	app: &'a App,
-	progress: Option<&'a ProgressBar>,
-	agent: ureq::Agent,
-	prices_bucket: Bucket<'a, String, String>,
+///
+/// One instance answers queries for any number of accounts and networks, sharing its HTTP client, rate limits and
+/// caches between them.
+pub struct Api {
+	config: ApiConfig,
+	client: Client,
+	price_provider: Box<dyn PriceProvider>,
+	prices_bucket: Bucket<'static, String, String>,
+	metadata_bucket: Bucket<'static, String, String>,
+	rewards_bucket: Bucket<'static, String, String>,
+	sync_bucket: Bucket<'static, String, String>,
+	/// networks with the token metadata of their chain, by id. Metadata is only fetched once per network.
+	networks: Mutex<HashMap<String, Network>>,
+	progress: Option<ProgressBar>,
 }
 
-impl<'a> Api<'a> {
+impl Api {
 	/// instantiate a new instance of the subscan API
-	pub fn new(app: &'a App, progress: Option<&'a ProgressBar>) -> Self {
-		let agent = ureq::builder().user_agent(&app.user).build();
+	pub fn new(config: ApiConfig) -> Result<Self, Error> {
+		ensure!(config.subscan_rps.is_none_or(|r| r > 0.0), "`subscan_rps` must be positive");
+		ensure!(config.price_rpm.is_none_or(|r| r > 0.0), "`price_rpm` must be positive");
+		ensure!(config.jobs > 0, "`jobs` must be positive");
+		let agent = ureq::builder().user_agent(&config.user_agent).build();
+		let client = Client::new(agent, RetryPolicy::new(config.max_attempts), subscan_rate(&config))
+			.with_fixtures(config.fixtures.clone());
+		let price_client = client.with_rate(match config.price_rpm {
+			Some(rpm) => Rate::per_minute(rpm),
+			None => config.price_source.default_rate(subscan_rate(&config)),
+		});
+		let price_url = config.price_url.as_deref();
+		let price_provider: Box<dyn PriceProvider> = match config.price_source {
+			PriceSource::CoinGecko => Box::new(CoinGecko::new(price_client, price_url)),
+			PriceSource::CryptoCompare => Box::new(CryptoCompare::new(price_client, price_url)),
+			PriceSource::Subscan => Box::new(price::Subscan::new(price_client, &config.api_key, price_url)),
+		};
 
-		let cfg = Config::new("./cache_store");
-		let cache_store = Store::new(cfg).expect("Failed to initialize cache store in ./cache_store");
-		let prices_bucket =
-			cache_store.bucket::<String, String>(Some("prices")).expect("Failed to crate a 'prices' bucket");
+		let cache_store = CacheStore::open(&config.cache_dir)?;
+		Ok(Self {
+			client,
+			price_provider,
+			prices_bucket: cache_store.bucket(cache::PRICES)?,
+			metadata_bucket: cache_store.bucket(cache::METADATA)?,
+			rewards_bucket: cache_store.bucket(cache::REWARDS)?,
+			sync_bucket: cache_store.bucket(cache::SYNC)?,
+			networks: Mutex::new(HashMap::new()),
+			progress: None,
+			config,
+		})
+	}
 
-		Self { app, progress, agent, prices_bucket }
+	/// Report the progress of fetching rewards and prices on `progress`.
+	pub fn with_progress(mut self, progress: Option<&ProgressBar>) -> Self {
+		self.progress = progress.cloned();
+		self
 	}
 
-	/// get a price at a point in time from subscan.
-	///
-	/// `time`: UNIX timestamp of the time to query (UTC)
-	fn price(&self, day: NaiveDate) -> Result<Price, Error> {
-		let key = format!("{} {}", self.app.network.id(), day.format("%Y-%m-%d"));
-		let price: Price = if let Some(raw_price) = self.prices_bucket.get(&key).unwrap() {
-			serde_json::from_str(&raw_price)?
-		} else {
-			let req = self.agent.get(&price_endpoint(&self.app.network, day));
-			let price: Price = req.send_bytes(&[])?.into_json()?;
-			self.prices_bucket
-				.set(&key, &serde_json::to_string(&price).expect("Failed to serialize freshly-deserialized"))?;
+	pub fn config(&self) -> &ApiConfig {
+		&self.config
+	}
 
-			// coingecko allows 50 requests per minute
-			// it seems to be a bit oversensitive. We therefore restrain ourselves
-			// to 30 requests a minute.
-			std::thread::sleep(std::time::Duration::from_millis(5000));
+	/// The network of `query`, with the token symbol and decimals of its chain, and the configured Subscan URL.
+	pub fn network(&self, query: &Query) -> Result<Network, Error> {
+		let mut networks = self.networks.lock().unwrap_or_else(|e| e.into_inner());
+		if let Some(network) = networks.get(query.network.id()) {
+			return Ok(network.clone());
+		}
+		let mut network = query.network.clone();
+		if let Some(url) = &self.config.subscan_url {
+			network.subscan_url = network::base_url(&url.replace(network::PLACEHOLDER, network.id()));
+		}
+		if self.config.source == Source::Subscan {
+			let subscan = Subscan::new(self.client.clone(), network.clone(), &query.address, &self.config.api_key);
+			update_token_metadata(&mut network, &subscan, &self.metadata_bucket)?;
+		}
+		networks.insert(network.id().to_string(), network.clone());
+		Ok(network)
+	}
 
-			price
-		};
-		Ok(price)
+	/// Where the rewards of `query` are read from.
+	fn source<'q>(&'q self, query: &'q Query) -> Result<Box<dyn RewardSource + 'q>, Error> {
+		Ok(match &self.config.source {
+			Source::Subscan => {
+				let subscan =
+					Subscan::new(self.client.clone(), self.network(query)?, &query.address, &self.config.api_key)
+						.with_jobs(self.config.jobs);
+				Box::new(Cached::new(
+					Box::new(subscan),
+					&query.network,
+					&query.address,
+					self.rewards_bucket.clone(),
+					self.sync_bucket.clone(),
+				))
+			}
+			Source::File(path) => Box::new(JsonFile::open(path)?),
+		})
 	}
 
-	/// Get rewards from a specific page of subscan API
+	/// get the price of the networks token on `day` in the configured currency.
 	///
-	/// `page`: Which page to query
-	/// `count`: How many to return in one request. There's some upper limit on this, probably something like 100
-	fn rewards(&self, page: usize, count: usize) -> Result<List<Reward>, Error> {
-		let req = self.agent.post(&get_endpoint(&self.app.network, REWARD_SLASH));
-
-		let rewards = req
-			.set("Content-Type", "application/json")
-			.set("X-API-Key", &self.app.api_key)
-			.send_json(ureq::json!({
-				"address": self.app.address.as_str(),
-				"page": page,
-				"row": count
-			}))
-			.with_context(|| {
-				format!("Failed to fetch reward for address={} page={page} row={count}", self.app.address)
-			})?
-			.into_string()?;
-		let rewards: ApiResponse<List<Reward>> =
-			serde_json::from_str(&rewards).with_context(|| format!("Failed to decode response: {rewards}"))?;
-		Ok(rewards.consume())
+	/// Prices are cached per provider, network and day.
+	fn price(&self, network: &Network, day: NaiveDate) -> Result<f64, Error> {
+		let currency = self.config.currency.to_lowercase();
+		let mut prices = self.cached_prices(network, day)?;
+		if !prices.contains_key(&currency) {
+			prices.extend(self.price_provider.prices(network, day, &currency)?);
+			self.cache_prices(network, day, &prices)?;
+		}
+		prices.get(&currency).copied().ok_or_else(|| {
+			err::Error::UnsupportedCurrency {
+				currency: self.config.currency.clone(),
+				provider: self.price_provider.id(),
+				supported: prices.keys().sorted().join(", "),
+			}
+			.into()
+		})
 	}
 
 	/// Fetch all rewardsstarting from some point in time and ending at another.
-	fn fetch_rewards(&self) -> Result<Vec<Reward>, Error> {
-		const PAGE_SIZE: usize = 100;
-
-		self.progress.map(|r| r.reset());
-		self.progress.map(|p| p.set_message("Fetching Rewards"));
-		self.progress.map(|r| r.tick());
-
-		let page_estimate = {
-			let num_entries = self.rewards(0, 1).context("Failed to fetch initial reward page")?.count;
-			let full_pages = num_entries / PAGE_SIZE;
-			if num_entries % PAGE_SIZE == 0 {
-				full_pages
-			} else {
-				full_pages + 1
-			}
-		};
-
-		self.progress.map(|p| p.set_message("Fetching Rewards"));
-		self.progress.map(|p| p.set_length(page_estimate.try_into().unwrap()));
-		self.progress.map(|r| r.tick());
+	fn fetch_rewards(&self, query: &Query) -> Result<Vec<Reward>, Error> {
+		let progress = self.progress.as_ref();
+		let source = self.source(query)?;
+		progress.map(|r| r.reset());
+		progress.map(|p| p.set_message("Fetching Rewards"));
+		// grows by the pages `fetch_range` is about to fetch, and stays empty if the cache covers the range
+		progress.map(|p| p.set_length(0));
+		progress.map(|r| r.tick());
 
-		let rewards: Vec<Reward> = (0..)
-			.map(|i| {
-				self.progress.map(|p| p.inc(1));
-				// subscan allows 5 requests per second.
-				std::thread::sleep(std::time::Duration::from_millis(300));
-				self.rewards(i, PAGE_SIZE).with_context(|| format!("Failed to fetch page {i}")).unwrap().list
-			})
-			.take_while(|list| list.as_ref().map_or(false, |l| !l.is_empty()))
-			.flatten()
-			.flatten()
-			.filter(|r| {
-				let timestamp = NaiveDateTime::from_timestamp_opt(r.block_timestamp.try_into().unwrap(), 0)
-					.expect("Block timestamp is out of range. This is a bug.");
-				let from = if let Some(from) = self.app.from { timestamp >= from } else { true };
-				let to = if let Some(to) = self.app.to { timestamp <= to } else { true };
-				from && to
-			})
-			.collect();
+		let rewards = source.fetch_range(query.from, query.to, progress)?;
 
-		self.progress.map(|p| p.finish());
+		progress.map(|p| p.finish());
 		Ok(rewards)
 	}
 
 	/// Fetch all rewards, joining blocks with rewards on the same day
-	pub fn fetch_all_rewards(&self) -> Result<Vec<RewardEntry>, Error> {
-		let rewards = self.fetch_rewards()?;
+	pub fn fetch_all_rewards(&self, query: &Query) -> Result<Vec<RewardEntry>, Error> {
+		let progress = self.progress.as_ref();
+		let rewards = self.fetch_rewards(query)?;
 		// TODO: this is kind of cheating but it's easier than trying to query just what we need
-		self.progress.map(|p| p.finish());
+		progress.map(|p| p.finish());
 
 		// merge all entries from the same day
 		let mut merged = BTreeMap::new();
@@ -183,21 +268,14 @@ impl<'a> Api<'a> {
 			let day = NaiveDateTime::from_timestamp_opt(reward.block_timestamp.try_into()?, 0)
 				.context("Block timestamp is out of range. This is a bug")?
 				.date();
-			let amount: u128 = reward.amount.parse()?;
-			let value = RewardEntry {
-				block_nums: {
-					let mut blocks = BTreeSet::new();
-					blocks.insert(reward.block_num);
-					blocks
-				},
-				day,
-				amount,
-			};
+			let (amount, slash) = reward.amounts()?;
+			let value = RewardEntry { block_nums: BTreeSet::from([reward.block_num]), day, amount, slash };
 			merged
 				.entry(day)
 				.and_modify(|e: &mut RewardEntry| {
 					e.block_nums.insert(reward.block_num);
 					e.amount += amount;
+					e.slash += slash;
 				})
 				.or_insert(value);
 		}
@@ -205,41 +283,183 @@ impl<'a> Api<'a> {
 		Ok(merged.into_values().rev().collect())
 	}
 
-	pub fn fetch_all_rewards_separated(&self) -> Result<Vec<SeparatedRewardEntry>, Error> {
+	pub fn fetch_all_rewards_separated(&self, query: &Query) -> Result<Vec<SeparatedRewardEntry>, Error> {
 		let mut separated_rewards = Vec::new();
-		let rewards = self.fetch_rewards()?;
+		let rewards = self.fetch_rewards(query)?;
 		for reward in rewards {
 			let date = NaiveDateTime::from_timestamp_opt(reward.block_timestamp.try_into()?, 0)
 				.context("Block timestamp is out of range. This is a bug")?;
-			let amount: u128 = reward.amount.parse()?;
-			let value =
-				SeparatedRewardEntry { block_num: reward.block_num, amount, day: date.date(), time: date.time() };
+			let (amount, slash) = reward.amounts()?;
+			let value = SeparatedRewardEntry {
+				block_num: reward.block_num,
+				extrinsic_hash: reward.extrinsic_hash,
+				amount,
+				slash,
+				day: date.date(),
+				time: date.time(),
+			};
 			separated_rewards.push(value);
 		}
 		Ok(separated_rewards)
 	}
 
-	/// Returns a vector of prices corresponding to the passed-in vector of Rewards.
-	pub fn fetch_prices(&self, dates: &[NaiveDate]) -> Result<Vec<f64>, Error> {
-		self.progress.map(|p| p.reset());
-		self.progress.map(|p| p.set_message("Fetching Price Data"));
-		self.progress.map(|p| p.set_length(dates.len().try_into().unwrap()));
-		self.progress.map(|r| r.tick());
-		let mut prices = Vec::with_capacity(dates.len());
-		for day in dates {
-			self.progress.map(|p| p.inc(1));
-			let result = self.price(*day)?;
-			let price = result.market_data.current_price.get(&self.app.currency).ok_or_else(|| {
-				anyhow!(
-					"Specified fiat currency '{}' not supported: {:#?}",
-					self.app.currency,
-					result.market_data.current_price.keys(),
-				)
-			})?;
-			prices.push(*price);
+	/// Rewards joined by day, with the price of each day if `with_prices` is set.
+	pub fn priced_rewards(&self, query: &Query, with_prices: bool) -> Result<Vec<Priced<RewardEntry>>, Error> {
+		let rewards = self.fetch_all_rewards(query).context("Failed to fetch rewards.")?;
+		let prices = if with_prices {
+			let dates: Vec<NaiveDate> = rewards.iter().map(|r| r.day).collect();
+			self.fetch_prices(&self.network(query)?, &dates)
+				.context("Failed to fetch prices.")?
+				.into_iter()
+				.map(Some)
+				.collect()
+		} else {
+			vec![None; rewards.len()]
+		};
+		Ok(rewards.into_iter().zip(prices).map(|(reward, price)| Priced { reward, price }).collect())
+	}
+
+	/// Every reward on its own, with the price of its day if `with_prices` is set.
+	pub fn priced_rewards_separated(
+		&self,
+		query: &Query,
+		with_prices: bool,
+	) -> Result<Vec<Priced<SeparatedRewardEntry>>, Error> {
+		let rewards = self.fetch_all_rewards_separated(query).context("Failed to fetch rewards.")?;
+		let prices: HashMap<NaiveDate, f64> = if with_prices {
+			let dates = rewards.iter().map(|r| r.day).unique().collect::<Vec<NaiveDate>>();
+			let prices = self.fetch_prices(&self.network(query)?, &dates).context("Failed to fetch prices.")?;
+			dates.into_iter().zip(prices).collect()
+		} else {
+			HashMap::new()
+		};
+		Ok(rewards
+			.into_iter()
+			.map(|reward| {
+				let price = prices.get(&reward.day).copied();
+				Priced { reward, price }
+			})
+			.collect())
+	}
+
+	/// The cached prices of the networks token on `day`, keyed by lowercase currency.
+	fn cached_prices(&self, network: &Network, day: NaiveDate) -> Result<HashMap<String, f64>, Error> {
+		Ok(match self.prices_bucket.get(&price_key(self.price_provider.id(), network, day))? {
+			Some(raw_prices) => serde_json::from_str(&raw_prices)?,
+			None => HashMap::new(),
+		})
+	}
+
+	fn cache_prices(&self, network: &Network, day: NaiveDate, prices: &HashMap<String, f64>) -> Result<(), Error> {
+		let prices = serde_json::to_string(prices).expect("Failed to serialize freshly-deserialized");
+		self.prices_bucket.set(&price_key(self.price_provider.id(), network, day), &prices)?;
+		Ok(())
+	}
+
+	/// Look up the uncached prices of `dates` with a single range request, and cache them per day.
+	///
+	/// Days the range has no price for are left to [`Api::price`], and so are all of them if the request fails.
+	fn fetch_price_range(&self, network: &Network, dates: &[NaiveDate]) -> Result<(), Error> {
+		let currency = self.config.currency.to_lowercase();
+		let mut missing = BTreeMap::new();
+		for day in dates.iter().copied().collect::<BTreeSet<_>>() {
+			let prices = self.cached_prices(network, day)?;
+			if !prices.contains_key(&currency) {
+				missing.insert(day, prices);
+			}
+		}
+		// a single day is just as well looked up on its own, which returns all currencies
+		let (from, to) = match (missing.keys().next(), missing.keys().next_back()) {
+			(Some(from), Some(to)) if from != to => (*from, *to),
+			_ => return Ok(()),
+		};
+		let range = match self.price_provider.range(network, from, to, &currency) {
+			Ok(range) => range,
+			Err(e) => {
+				log::warn!("Failed to fetch the prices from {from} to {to} at once, fetching them day by day: {e:#}");
+				return Ok(());
+			}
+		};
+		for (day, mut prices) in missing {
+			if let Some(price) = range.get(&day) {
+				prices.insert(currency.clone(), *price);
+				self.cache_prices(network, day, &prices)?;
+			}
 		}
-		self.progress.map(|p| p.finish_with_message("Prices Fetched"));
+		Ok(())
+	}
+
+	/// Returns a vector of prices of the token of `network` corresponding to the passed-in vector of Rewards.
+	///
+	/// Uncached days are fetched with one range request if the provider supports it, and one by one otherwise.
+	pub fn fetch_prices(&self, network: &Network, dates: &[NaiveDate]) -> Result<Vec<f64>, Error> {
+		let progress = self.progress.as_ref();
+		progress.map(|p| p.reset());
+		progress.map(|p| p.set_message("Fetching Price Data"));
+		progress.map(|p| p.set_length(dates.len() as u64));
+		progress.map(|r| r.tick());
+		self.fetch_price_range(network, dates)?;
+		// cached days are looked up on the pool as well, they're cheap
+		let prices = pool::map(dates, self.config.jobs, |day| {
+			progress.map(|p| p.inc(1));
+			self.price(network, *day)
+		})?;
+		progress.map(|p| p.finish_with_message("Prices Fetched"));
 		self.prices_bucket.flush()?;
 		Ok(prices)
 	}
 }
+
+/// Key of the prices of `day` in the price cache.
+fn price_key(provider: &str, network: &Network, day: NaiveDate) -> String {
+	format!("{provider} {} {}", network.id(), day.format("%Y-%m-%d"))
+}
+
+/// How many requests to send to Subscan. Subscan allows more requests with an API key.
+fn subscan_rate(config: &ApiConfig) -> Rate {
+	match config.subscan_rps {
+		Some(rps) => Rate::per_second(rps),
+		None if config.api_key.is_empty() => Rate::per_second(SUBSCAN_RPS),
+		None => Rate::per_second(SUBSCAN_RPS_WITH_KEY),
+	}
+}
+
+/// Update the token symbol and decimals of `network` with the ones in the chain metadata.
+///
+/// Falls back to the last metadata seen for the network, and then to the network registry, if subscan can't be reached.
+fn update_token_metadata(
+	network: &mut Network,
+	subscan: &Subscan,
+	bucket: &Bucket<String, String>,
+) -> Result<(), Error> {
+	let key = network.id().to_string();
+	let token = match subscan.token() {
+		Ok(token) => {
+			bucket.set(&key, &serde_json::to_string(&token)?)?;
+			bucket.flush()?;
+			token
+		}
+		Err(e) => match bucket.get(&key)? {
+			Some(cached) => {
+				log::warn!("Using cached token metadata for {key}: {e:?}");
+				serde_json::from_str(&cached)?
+			}
+			None => {
+				log::warn!("Using built-in token metadata for {key}: {e:?}");
+				return Ok(());
+			}
+		},
+	};
+	if token.token_decimals != network.decimals || token.symbol != network.symbol {
+		log::warn!(
+			"Token of {key} is {} with {} decimals according to its metadata, instead of {} with {} decimals",
+			token.symbol,
+			token.token_decimals,
+			network.symbol,
+			network.decimals
+		);
+	}
+	network.symbol = token.symbol;
+	network.decimals = token.token_decimals;
+	Ok(())
+}
@@ -0,0 +1,69 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of polkadot-rewards.
+
+// polkadot-rewards is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// polkadot-rewards is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with polkadot-rewards.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Lists of accounts to crawl rewards for in one run.
+
+use anyhow::{ensure, Context, Error};
+use serde::Deserialize;
+use std::{fs, path::Path};
+
+/// An account listed in an addresses file.
+#[derive(Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct Account {
+	/// address of the account. Either network-formatted, generic (prefix 42), or a hex public key.
+	pub address: String,
+	/// network of the account. Defaults to the `--network` option, or the network inferred from the address.
+	#[serde(default)]
+	pub network: Option<String>,
+	/// human-readable name of the account. Defaults to the address.
+	#[serde(default)]
+	pub label: Option<String>,
+}
+
+impl Account {
+	pub fn label(&self) -> &str {
+		self.label.as_deref().filter(|l| !l.is_empty()).unwrap_or(&self.address)
+	}
+}
+
+#[derive(Deserialize, Debug)]
+struct AccountsFile {
+	#[serde(default)]
+	account: Vec<Account>,
+}
+
+/// Read the accounts from a `.toml` file with a list of `[[account]]` tables,
+/// or from a CSV file with an `address,network,label` header.
+pub fn load(path: &Path) -> Result<Vec<Account>, Error> {
+	let accounts = if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("toml")) {
+		let contents =
+			fs::read_to_string(path).with_context(|| format!("Failed to read addresses file {}", path.display()))?;
+		let file: AccountsFile =
+			toml::from_str(&contents).with_context(|| format!("Failed to parse addresses file {}", path.display()))?;
+		file.account
+	} else {
+		csv::ReaderBuilder::new()
+			.trim(csv::Trim::All)
+			.flexible(true)
+			.from_path(path)
+			.with_context(|| format!("Failed to read addresses file {}", path.display()))?
+			.deserialize()
+			.collect::<Result<Vec<Account>, _>>()
+			.with_context(|| format!("Failed to parse addresses file {}", path.display()))?
+	};
+	ensure!(!accounts.is_empty(), "No accounts found in {}", path.display());
+	Ok(accounts)
+}
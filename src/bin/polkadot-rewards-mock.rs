@@ -0,0 +1,177 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of polkadot-rewards.
+
+// polkadot-rewards is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// polkadot-rewards is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with polkadot-rewards.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A stand-in for Subscan and the price sources, serving fixtures recorded with `--record`.
+//!
+//! Point `--subscan-url` and `--price-url` at it to run without network access. Every fixture is served under the host
+//! it was recorded from: `https://polkadot.api.subscan.io/api/scan/token` at `/polkadot.api.subscan.io/api/scan/token`.
+//! Requests are answered with the fixture of the same method, body, and exact path and query, so one mock serves the
+//! fixtures of several networks and CoinGecko without mixing them up.
+
+use anyhow::{Context, Error};
+use argh::FromArgs;
+use env_logger::{Builder, Env};
+use polkadot_rewards::fixtures::{self, Fixture};
+use std::{
+	collections::BTreeSet,
+	io::{BufRead, BufReader, Read, Write},
+	net::{TcpListener, TcpStream},
+	path::PathBuf,
+	sync::Arc,
+	thread,
+};
+
+#[derive(FromArgs, Debug)]
+/// Serve recorded fixtures as a stand-in for Subscan and CoinGecko.
+struct Mock {
+	/// directory of fixtures recorded with `--record`.
+	#[argh(positional)]
+	fixtures: PathBuf,
+	/// address to listen on. Port 0 picks a free port. Default: 127.0.0.1:8080
+	#[argh(option, default = "String::from(\"127.0.0.1:8080\")")]
+	listen: String,
+}
+
+fn main() -> Result<(), Error> {
+	let mock: Mock = argh::from_env();
+	Builder::from_env(Env::default().default_filter_or("info")).init();
+	let fixtures = Arc::new(fixtures::load(&mock.fixtures)?);
+	let listener = TcpListener::bind(&mock.listen).with_context(|| format!("Failed to listen on {}", mock.listen))?;
+	// printed on its own line, so scripts can wait for it and pick up the port
+	println!("Serving {} fixtures on http://{}", fixtures.len(), listener.local_addr()?);
+	for host in
+		fixtures.iter().filter_map(|f| route(&f.url).split('/').nth(1).map(String::from)).collect::<BTreeSet<_>>()
+	{
+		log::info!("Serving {host} at http://{}/{host}", listener.local_addr()?);
+	}
+	for stream in listener.incoming() {
+		let stream = stream?;
+		let fixtures = fixtures.clone();
+		thread::spawn(move || {
+			if let Err(e) = handle(stream, &fixtures) {
+				log::warn!("Failed to answer request: {e:?}");
+			}
+		});
+	}
+	Ok(())
+}
+
+/// Answer one HTTP/1.1 request, and close the connection.
+fn handle(mut stream: TcpStream, fixtures: &[Fixture]) -> Result<(), Error> {
+	let mut reader = BufReader::new(stream.try_clone()?);
+	let mut line = String::new();
+	reader.read_line(&mut line)?;
+	let mut parts = line.split_whitespace();
+	let (method, target) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+
+	let mut length = 0;
+	loop {
+		let mut header = String::new();
+		reader.read_line(&mut header)?;
+		match header.trim_end().split_once(':') {
+			Some((name, value)) if name.eq_ignore_ascii_case("content-length") => length = value.trim().parse()?,
+			Some(_) => {}
+			None => break,
+		}
+	}
+	let mut body = vec![0; length];
+	reader.read_exact(&mut body)?;
+	let request = serde_json::from_slice::<serde_json::Value>(&body).ok();
+
+	let (status, body) = match find(fixtures, method, target, request.as_ref()) {
+		Some(fixture) => (fixture.status, fixture.body()),
+		None => (404, format!("No fixture for {method} {target} {}", String::from_utf8_lossy(&body))),
+	};
+	log::info!("{method} {target} -> {status}");
+	write!(
+		stream,
+		"HTTP/1.1 {status} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+		reason(status),
+		body.len()
+	)?;
+	Ok(())
+}
+
+/// The fixture of a request to `target`, the path and query of the URL.
+fn find<'a>(
+	fixtures: &'a [Fixture],
+	method: &str,
+	target: &str,
+	request: Option<&serde_json::Value>,
+) -> Option<&'a Fixture> {
+	fixtures.iter().find(|f| f.method == method && route(&f.url) == target && f.request.as_ref() == request)
+}
+
+/// Where the fixture of `url` is served: `/{host}{path}?{query}`.
+fn route(url: &str) -> String {
+	format!("/{}", url.split_once("://").map_or(url, |(_, rest)| rest))
+}
+
+fn reason(status: u16) -> &'static str {
+	match status {
+		200 => "OK",
+		400 => "Bad Request",
+		401 => "Unauthorized",
+		403 => "Forbidden",
+		404 => "Not Found",
+		429 => "Too Many Requests",
+		500 => "Internal Server Error",
+		_ => "Unknown",
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn fixture(url: &str) -> Fixture {
+		Fixture {
+			method: "GET".into(),
+			url: url.into(),
+			request: None,
+			status: 200,
+			response: serde_json::Value::String(url.into()),
+		}
+	}
+
+	#[test]
+	fn fixtures_are_found_by_host_path_and_query() {
+		let fixtures = [
+			fixture("https://polkadot.api.subscan.io/api/scan/token"),
+			fixture("https://kusama.api.subscan.io/api/scan/token"),
+			fixture("https://api.coingecko.com/api/v3/coins/polkadot/history?date=01-01-2023"),
+		];
+		let found = |target| find(&fixtures, "GET", target, None).map(|f| f.url.as_str());
+		assert_eq!(
+			found("/kusama.api.subscan.io/api/scan/token"),
+			Some("https://kusama.api.subscan.io/api/scan/token")
+		);
+		assert_eq!(
+			found("/api.coingecko.com/api/v3/coins/polkadot/history?date=01-01-2023"),
+			Some("https://api.coingecko.com/api/v3/coins/polkadot/history?date=01-01-2023")
+		);
+		for target in [
+			"/",
+			"/api/scan/token",
+			"/westend.api.subscan.io/api/scan/token",
+			"/api.coingecko.com/api/v3/coins/polkadot/history",
+			"/api.coingecko.com/api/v3/coins/polkadot/history?date=02-01-2023",
+		] {
+			assert_eq!(found(target), None, "{}", target);
+		}
+		assert_eq!(find(&fixtures, "POST", "/polkadot.api.subscan.io/api/scan/token", None).map(|f| &f.url), None);
+	}
+}
@@ -0,0 +1,568 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of polkadot-rewards.
+
+// polkadot-rewards is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// polkadot-rewards is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with polkadot-rewards.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Local cache of prices, token metadata and rewards.
+//!
+//! Rewards are cached so only rewards newer than the last run have to be fetched.
+
+use crate::{
+	network::Network,
+	primitives::{List, Reward},
+	source::{for_each_page, pages_from, timestamp, RewardSource, PAGE_SIZE},
+};
+use anyhow::{ensure, Context, Error};
+use chrono::{naive::NaiveDateTime, NaiveDate};
+use indicatif::ProgressBar;
+use kv::{Batch, Bucket, Config, Store};
+use serde::{Deserialize, Serialize};
+use std::{
+	collections::BTreeMap,
+	path::{Path, PathBuf},
+};
+
+/// prices, keyed by `"{provider} {network} {YYYY-MM-DD}"`
+pub const PRICES: &str = "prices";
+/// token metadata, keyed by network
+pub const METADATA: &str = "metadata";
+/// rewards, keyed by `"{network} {address} {event_index}"`
+pub const REWARDS: &str = "rewards";
+/// how much of the reward history of an account is cached, keyed by `"{network} {address}"`
+pub const SYNC: &str = "sync";
+pub const BUCKETS: [&str; 4] = [PRICES, METADATA, REWARDS, SYNC];
+
+/// The default directory of the cache: `$XDG_CACHE_HOME/polkadot-rewards`.
+///
+/// Falls back to `./cache_store`, where the cache used to live, if `XDG_CACHE_HOME` is not set.
+pub fn default_dir() -> PathBuf {
+	std::env::var_os("XDG_CACHE_HOME")
+		.filter(|dir| !dir.is_empty())
+		.map(|dir| PathBuf::from(dir).join("polkadot-rewards"))
+		.unwrap_or_else(|| PathBuf::from("./cache_store"))
+}
+
+/// Cache entries of every bucket, as exported to JSON.
+pub type Export = BTreeMap<String, BTreeMap<String, serde_json::Value>>;
+
+/// A bucket and its name.
+type NamedBucket<'a> = (&'static str, Bucket<'a, String, String>);
+
+/// The on-disk cache. Every bucket maps keys to JSON values.
+pub struct CacheStore {
+	store: Store,
+}
+
+impl CacheStore {
+	pub fn open(dir: &Path) -> Result<Self, Error> {
+		let store = Store::new(Config::new(dir))
+			.with_context(|| format!("Failed to initialize cache store in {}", dir.display()))?;
+		Ok(Self { store })
+	}
+
+	pub fn bucket<'a>(&self, name: &str) -> Result<Bucket<'a, String, String>, Error> {
+		ensure!(BUCKETS.contains(&name), "Bucket must be one of: {}", BUCKETS.join(", "));
+		self.store.bucket::<String, String>(Some(name)).with_context(|| format!("Failed to create a '{name}' bucket"))
+	}
+
+	pub fn path(&self) -> Result<PathBuf, Error> {
+		Ok(self.store.path()?.to_path_buf())
+	}
+
+	pub fn size_on_disk(&self) -> Result<u64, Error> {
+		Ok(self.store.size_on_disk()?)
+	}
+
+	/// The given bucket, or all buckets if `None`.
+	fn buckets<'a>(&self, bucket: Option<&str>) -> Result<Vec<NamedBucket<'a>>, Error> {
+		BUCKETS
+			.iter()
+			.filter(|name| bucket.is_none_or(|b| b == **name))
+			.map(|name| Ok((*name, self.bucket(name)?)))
+			.collect::<Result<Vec<_>, Error>>()
+			.and_then(|buckets| {
+				ensure!(!buckets.is_empty(), "Bucket must be one of: {}", BUCKETS.join(", "));
+				Ok(buckets)
+			})
+	}
+
+	/// Number of entries in each bucket.
+	pub fn stats(&self) -> Result<Vec<(&'static str, usize)>, Error> {
+		Ok(self.buckets(None)?.into_iter().map(|(name, bucket)| (name, bucket.len())).collect())
+	}
+
+	/// Keys of all entries, by bucket.
+	pub fn list(&self, bucket: Option<&str>) -> Result<Vec<(&'static str, String)>, Error> {
+		let mut keys = Vec::new();
+		for (name, bucket) in self.buckets(bucket)? {
+			for item in bucket.iter() {
+				keys.push((name, item?.key()?));
+			}
+		}
+		Ok(keys)
+	}
+
+	/// Remove all entries of `network` (or of all networks), dated before `before` (or of any date).
+	///
+	/// Entries without a date, like token metadata, are only removed if `before` is not given.
+	/// Returns the number of removed entries.
+	pub fn purge(&self, network: Option<&str>, before: Option<NaiveDate>) -> Result<usize, Error> {
+		let is_network = |n: Option<&str>| network.is_none_or(|network| n == Some(network));
+		let is_before = |day: Option<NaiveDate>| match before {
+			Some(before) => day.is_some_and(|day| day < before),
+			None => true,
+		};
+		let mut removed = 0;
+		for (name, bucket) in self.buckets(None)? {
+			for item in bucket.iter() {
+				let item = item?;
+				let key: String = item.key()?;
+				let mut parts = key.split(' ');
+				let purge = match name {
+					PRICES => {
+						let (_, network, day) = (parts.next(), parts.next(), parts.next());
+						is_network(network) && is_before(day.and_then(|d| d.parse().ok()))
+					}
+					METADATA => is_network(Some(&key)) && before.is_none(),
+					REWARDS => {
+						let reward = serde_json::from_str::<Reward>(&item.value::<String>()?).ok();
+						let day = reward.and_then(|r| timestamp(&r).ok()).map(|t| t.date());
+						is_network(parts.next()) && is_before(day)
+					}
+					SYNC if is_network(parts.next()) => match before {
+						None => true,
+						Some(before) => {
+							// rewards before `before` are gone, so the cache only covers the rewards after it
+							let mut state: SyncState = serde_json::from_str(&item.value::<String>()?)?;
+							let before = before.and_hms_opt(0, 0, 0).expect("midnight is a valid time");
+							state.covered_from = Some(state.covered_from.map_or(before, |c| c.max(before)));
+							bucket.set(&key, &serde_json::to_string(&state)?)?;
+							false
+						}
+					},
+					_ => false,
+				};
+				if purge {
+					bucket.remove(&key)?;
+					removed += 1;
+				}
+			}
+			bucket.flush()?;
+		}
+		Ok(removed)
+	}
+
+	/// All entries of the given bucket, or of all buckets if `None`.
+	pub fn export(&self, bucket: Option<&str>) -> Result<Export, Error> {
+		let mut export = Export::new();
+		for (name, bucket) in self.buckets(bucket)? {
+			let entries = export.entry(name.to_string()).or_default();
+			for item in bucket.iter() {
+				let item = item?;
+				entries.insert(item.key()?, serde_json::from_str(&item.value::<String>()?)?);
+			}
+		}
+		Ok(export)
+	}
+
+	/// Add the entries of an export, replacing existing entries with the same key.
+	///
+	/// Returns the number of imported entries.
+	pub fn import(&self, export: Export) -> Result<usize, Error> {
+		let mut imported = 0;
+		for (name, entries) in export {
+			let bucket = self.bucket(&name)?;
+			let mut batch = Batch::new();
+			for (key, value) in entries {
+				batch.set(&key, &serde_json::to_string(&value)?)?;
+				imported += 1;
+			}
+			bucket.batch(batch)?;
+			bucket.flush()?;
+		}
+		Ok(imported)
+	}
+}
+
+/// How much of the history of an account is cached.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct SyncState {
+	/// All rewards at or after this time are cached. `None` if the whole history is cached.
+	covered_from: Option<NaiveDateTime>,
+	/// All rewards at or before this time are cached. `None` if the cache reaches the newest reward.
+	#[serde(default)]
+	covered_to: Option<NaiveDateTime>,
+}
+
+impl SyncState {
+	/// Whether all rewards between `from` and `to` are cached, apart from rewards newer than the newest cached one.
+	fn covers(&self, from: Option<NaiveDateTime>, to: Option<NaiveDateTime>) -> bool {
+		let from = match (self.covered_from, from) {
+			(None, _) => true,
+			(Some(covered_from), Some(from)) => covered_from <= from,
+			(Some(_), None) => false,
+		};
+		let to = match (self.covered_to, to) {
+			(None, _) => true,
+			(Some(covered_to), Some(to)) => to <= covered_to,
+			(Some(_), None) => false,
+		};
+		from && to
+	}
+}
+
+/// Caches the rewards of another source, keyed by network, address and event index.
+///
+/// The cache always holds a contiguous range of rewards: from the newest reward back to some point in time or, if the
+/// first run of an account asked for rewards up to `--to`, a window around that range. A window is joined to the newest
+/// rewards by the first run which asks for them.
+pub struct Cached<'a> {
+	inner: Box<dyn RewardSource + 'a>,
+	rewards_bucket: Bucket<'a, String, String>,
+	sync_bucket: Bucket<'a, String, String>,
+	/// `"{network} {address}"`
+	account: String,
+}
+
+impl<'a> Cached<'a> {
+	pub fn new(
+		inner: Box<dyn RewardSource + 'a>,
+		network: &Network,
+		address: &str,
+		rewards_bucket: Bucket<'a, String, String>,
+		sync_bucket: Bucket<'a, String, String>,
+	) -> Self {
+		Self { inner, rewards_bucket, sync_bucket, account: format!("{} {address}", network.id()) }
+	}
+
+	fn key(&self, reward: &Reward) -> String {
+		format!("{} {}", self.account, reward.event_index)
+	}
+
+	fn state(&self) -> Result<Option<SyncState>, Error> {
+		self.sync_bucket.get(&self.account)?.map(|s| serde_json::from_str(&s)).transpose().map_err(Into::into)
+	}
+
+	fn save(&self, state: &SyncState) -> Result<(), Error> {
+		self.sync_bucket.set(&self.account, &serde_json::to_string(state)?)?;
+		Ok(())
+	}
+
+	fn insert(&self, reward: &Reward) -> Result<(), Error> {
+		self.rewards_bucket.set(&self.key(reward), &serde_json::to_string(reward)?)?;
+		Ok(())
+	}
+
+	/// All cached rewards of the account.
+	fn cached(&self) -> Result<Vec<Reward>, Error> {
+		self.rewards_bucket
+			.iter_prefix(&format!("{} ", self.account))?
+			.map(|item| Ok(serde_json::from_str(&item?.value::<String>()?)?))
+			.collect()
+	}
+
+	fn is_empty(&self) -> Result<bool, Error> {
+		Ok(self.rewards_bucket.iter_prefix(&format!("{} ", self.account))?.next().is_none())
+	}
+
+	/// Number of cached rewards of the account.
+	fn len(&self) -> Result<usize, Error> {
+		Ok(self.rewards_bucket.iter_prefix(&format!("{} ", self.account))?.count())
+	}
+
+	/// Remove all cached rewards of the account.
+	fn clear(&self) -> Result<(), Error> {
+		let mut batch = Batch::new();
+		for item in self.rewards_bucket.iter_prefix(&format!("{} ", self.account))? {
+			batch.remove(&item?.key()?)?;
+		}
+		self.rewards_bucket.batch(batch)?;
+		Ok(())
+	}
+
+	/// Fetch rewards newer than the newest cached reward.
+	fn sync_head(&self, progress: Option<&ProgressBar>) -> Result<(), Error> {
+		let cached = self.len()?;
+		// the new rewards, and the page with the newest cached reward
+		let expected = move |count: usize| count.saturating_sub(cached) / PAGE_SIZE + 1;
+		let mut new = Vec::new();
+		for_each_page(&*self.inner, 0, progress, expected, |list| {
+			for reward in list {
+				if self.rewards_bucket.contains(&self.key(&reward))? {
+					return Ok(false);
+				}
+				new.push(reward);
+			}
+			Ok(true)
+		})?;
+		// inserted all at once, so the cache never has a gap between old and new rewards.
+		let mut batch = Batch::new();
+		for reward in &new {
+			batch.set(&self.key(reward), &serde_json::to_string(reward)?)?;
+		}
+		self.rewards_bucket.batch(batch)?;
+		Ok(())
+	}
+
+	/// Hand the pages from `start` on to `keep`, up to the first page reaching past `from`.
+	///
+	/// Returns from when on all rewards were handed over, `None` if the page with the oldest reward was reached.
+	fn crawl(
+		&self,
+		start: usize,
+		from: Option<NaiveDateTime>,
+		progress: Option<&ProgressBar>,
+		mut keep: impl FnMut(&[Reward]) -> Result<(), Error>,
+	) -> Result<Option<NaiveDateTime>, Error> {
+		let mut reached_from = false;
+		for_each_page(&*self.inner, start, progress, pages_from(start), |list| {
+			let oldest = list.last().map(timestamp).transpose()?;
+			keep(&list)?;
+			reached_from = from.zip(oldest).is_some_and(|(from, oldest)| oldest < from);
+			Ok(!reached_from)
+		})?;
+		Ok(if reached_from { from } else { None })
+	}
+
+	/// Fetch rewards older than the oldest cached reward, back to `from`.
+	fn backfill(&self, from: Option<NaiveDateTime>, progress: Option<&ProgressBar>) -> Result<SyncState, Error> {
+		// new rewards only ever push older ones onto later pages, so we can't miss any by starting here.
+		let cached = self.len()?;
+		let covered_from = self
+			.crawl(cached / PAGE_SIZE, from, progress, |list| list.iter().try_for_each(|reward| self.insert(reward)))?;
+		Ok(SyncState { covered_from, covered_to: None })
+	}
+
+	/// Fetch the rewards between `from` and `to`, skipping the pages of newer rewards.
+	fn window(
+		&self,
+		from: Option<NaiveDateTime>,
+		to: NaiveDateTime,
+		progress: Option<&ProgressBar>,
+	) -> Result<SyncState, Error> {
+		let start = self.inner.first_page_until(to)?;
+		// inserted all at once, so an interrupted run doesn't leave a window which is taken for the newest rewards.
+		let mut batch = Batch::new();
+		let covered_from = self.crawl(start, from, progress, |list| {
+			list.iter().try_for_each(|reward| Ok(batch.set(&self.key(reward), &serde_json::to_string(reward)?)?))
+		})?;
+		self.rewards_bucket.batch(batch)?;
+		// the page before `start` has a reward newer than `to`, so no reward up to `to` can be missing later on.
+		Ok(SyncState { covered_from, covered_to: (start > 0).then_some(to) })
+	}
+}
+
+impl<'a> RewardSource for Cached<'a> {
+	fn rewards(&self, page: usize, count: usize) -> Result<List<Reward>, Error> {
+		self.inner.rewards(page, count)
+	}
+
+	fn jobs(&self) -> usize {
+		self.inner.jobs()
+	}
+
+	/// The number of cached rewards if the last run cached the whole history, without a request.
+	fn count(&self) -> Result<usize, Error> {
+		match self.state()? {
+			Some(state) if state.covers(None, None) => self.len(),
+			_ => self.inner.count(),
+		}
+	}
+
+	fn fetch_range(
+		&self,
+		from: Option<NaiveDateTime>,
+		to: Option<NaiveDateTime>,
+		progress: Option<&ProgressBar>,
+	) -> Result<Vec<Reward>, Error> {
+		let state = self.state()?;
+		let window = state.as_ref().is_some_and(|s| s.covered_to.is_some());
+		let cold = state.is_none() && self.is_empty()?;
+		if window && state.as_ref().is_some_and(|s| s.covers(from, to)) {
+			// the window holds every reward of the range
+		} else if let Some(to) = to.filter(|_| cold || window) {
+			// only fetch the pages of the range, instead of everything newer than it
+			self.clear()?;
+			let state = self.window(from, to, progress)?;
+			self.save(&state)?;
+		} else {
+			// joins a window to the newest rewards, as it stops at the newest cached reward
+			if !self.is_empty()? {
+				self.sync_head(progress)?;
+			}
+			let joined = state.map(|state| SyncState { covered_to: None, ..state });
+			let state = match joined {
+				Some(state) if state.covers(from, None) => state,
+				_ => self.backfill(from, progress)?,
+			};
+			self.save(&state)?;
+		}
+		self.rewards_bucket.flush()?;
+		self.sync_bucket.flush()?;
+
+		let mut rewards = self
+			.cached()?
+			.into_iter()
+			.map(|r| Ok((timestamp(&r)?, r)))
+			.filter(|r| match r {
+				Ok((timestamp, _)) => from.is_none_or(|f| *timestamp >= f) && to.is_none_or(|t| *timestamp <= t),
+				Err(_) => true,
+			})
+			.collect::<Result<Vec<_>, Error>>()?;
+		rewards.sort_by_key(|(timestamp, r)| std::cmp::Reverse((*timestamp, r.block_num, r.event_idx)));
+		Ok(rewards.into_iter().map(|(_, r)| r).collect())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		network::Registry,
+		source::tests::{indices, time, Fake},
+	};
+	use tempfile::TempDir;
+
+	/// `source`, cached in `dir`.
+	fn cached<'a>(dir: &TempDir, source: &'a Fake) -> Cached<'a> {
+		let store = CacheStore::open(dir.path()).unwrap();
+		let network = Registry::builtin().get("polkadot").unwrap().clone();
+		Cached::new(Box::new(source), &network, "address", store.bucket(REWARDS).unwrap(), store.bucket(SYNC).unwrap())
+	}
+
+	/// Fetch the rewards of `source` between `from` and `to`, through the cache in `dir`.
+	fn fetch(dir: &TempDir, source: &Fake, from: Option<NaiveDateTime>, to: Option<NaiveDateTime>) -> Vec<usize> {
+		indices(&cached(dir, source).fetch_range(from, to, None).unwrap())
+	}
+
+	/// Fetch the rewards of `source` between `from` and `to`, and the length and position of the progress bar.
+	fn progress(dir: &TempDir, source: &Fake, from: Option<NaiveDateTime>, to: Option<NaiveDateTime>) -> (u64, u64) {
+		let progress = ProgressBar::hidden();
+		progress.set_length(0);
+		cached(dir, source).fetch_range(from, to, Some(&progress)).unwrap();
+		(progress.length().unwrap(), progress.position())
+	}
+
+	#[test]
+	fn only_new_rewards_are_fetched_again() {
+		let dir = TempDir::new().unwrap();
+		let source = Fake::new(350);
+		assert_eq!(fetch(&dir, &source, None, None).len(), 350);
+		assert_eq!(source.pages(), vec![0, 1, 2, 3]);
+		assert_eq!(fetch(&dir, &source, None, None).len(), 350);
+		assert_eq!(source.pages(), vec![0]);
+		source.push_newest();
+		assert_eq!(fetch(&dir, &source, None, None).len(), 351);
+		assert_eq!(source.pages(), vec![0]);
+	}
+
+	#[test]
+	fn a_cold_cache_only_fetches_the_pages_up_to_to() {
+		let dir = TempDir::new().unwrap();
+		let source = Fake::new(350);
+		assert_eq!(fetch(&dir, &source, None, Some(time(250))), (250..350).collect::<Vec<_>>());
+		assert_eq!(source.pages(), vec![2, 3]);
+		// served from the cache, without a single request
+		assert_eq!(fetch(&dir, &source, Some(time(300)), Some(time(260))), (260..=300).collect::<Vec<_>>());
+		assert_eq!(source.requests(), vec![]);
+	}
+
+	#[test]
+	fn a_window_is_joined_to_the_newest_rewards() {
+		let dir = TempDir::new().unwrap();
+		let source = Fake::new(350);
+		fetch(&dir, &source, Some(time(260)), Some(time(130)));
+		assert_eq!(source.pages(), vec![1, 2]);
+		// stops at the newest reward of the window, and doesn't refetch the window
+		assert_eq!(fetch(&dir, &source, Some(time(260)), None), (0..=260).collect::<Vec<_>>());
+		assert_eq!(source.pages(), vec![0, 1]);
+		// the rest of the history is fetched from where the cache ends
+		assert_eq!(fetch(&dir, &source, None, None).len(), 350);
+		assert_eq!(source.pages(), vec![0, 3]);
+	}
+
+	#[test]
+	fn a_window_of_another_range_is_replaced() {
+		let dir = TempDir::new().unwrap();
+		let source = Fake::new(350);
+		fetch(&dir, &source, Some(time(260)), Some(time(130)));
+		source.requests();
+		assert_eq!(fetch(&dir, &source, Some(time(340)), Some(time(320))), (320..=340).collect::<Vec<_>>());
+		assert_eq!(source.pages(), vec![3]);
+		assert_eq!(fetch(&dir, &source, Some(time(330)), Some(time(325))), (325..=330).collect::<Vec<_>>());
+		assert_eq!(source.requests(), vec![]);
+	}
+
+	#[test]
+	fn a_range_up_to_the_newest_reward_is_cached_like_the_whole_history() {
+		let dir = TempDir::new().unwrap();
+		let source = Fake::new(350);
+		assert_eq!(fetch(&dir, &source, Some(time(150)), Some(time(50))), (50..=150).collect::<Vec<_>>());
+		assert_eq!(source.pages(), vec![0, 1]);
+		source.push_newest();
+		assert_eq!(fetch(&dir, &source, Some(time(150)), None).len(), 152);
+		assert_eq!(source.pages(), vec![0]);
+	}
+
+	#[test]
+	fn backfilling_starts_where_the_cache_ends() {
+		let dir = TempDir::new().unwrap();
+		let source = Fake::new(350);
+		assert_eq!(fetch(&dir, &source, Some(time(150)), None), (0..=150).collect::<Vec<_>>());
+		assert_eq!(source.pages(), vec![0, 1]);
+		assert_eq!(fetch(&dir, &source, Some(time(320)), None), (0..=320).collect::<Vec<_>>());
+		assert_eq!(source.pages(), vec![0, 2, 3]);
+	}
+
+	#[test]
+	fn new_rewards_are_fetched_in_waves() {
+		let dir = TempDir::new().unwrap();
+		let source = Fake::new(350).with_jobs(4);
+		fetch(&dir, &source, None, Some(time(250)));
+		source.requests();
+		(0..250).for_each(|_| source.push_newest());
+		assert_eq!(fetch(&dir, &source, None, None).len(), 600);
+		// the newest reward of the window is on page 4 now
+		let mut pages = source.pages();
+		pages.sort();
+		assert_eq!(pages, vec![0, 1, 2, 3, 4]);
+	}
+
+	#[test]
+	fn the_count_of_a_cached_history_needs_no_request() {
+		let dir = TempDir::new().unwrap();
+		let source = Fake::new(350);
+		fetch(&dir, &source, Some(time(150)), None);
+		source.requests();
+		// only part of the history is cached
+		assert_eq!(cached(&dir, &source).count().unwrap(), 350);
+		assert_eq!(source.requests(), vec![(0, 1)]);
+		fetch(&dir, &source, None, None);
+		source.requests();
+		assert_eq!(cached(&dir, &source).count().unwrap(), 350);
+		assert_eq!(source.requests(), vec![]);
+	}
+
+	#[test]
+	fn progress_counts_the_pages_which_are_fetched() {
+		let dir = TempDir::new().unwrap();
+		let source = Fake::new(350);
+		assert_eq!(progress(&dir, &source, None, Some(time(250))), (2, 2));
+		assert_eq!(progress(&dir, &source, Some(time(300)), Some(time(260))), (0, 0));
+		// the window reaches back to the oldest reward, so joining it only fetches the pages up to it
+		assert_eq!(progress(&dir, &source, None, None), (3, 3));
+		(0..150).for_each(|_| source.push_newest());
+		assert_eq!(progress(&dir, &source, None, None), (2, 2));
+	}
+}
@@ -14,23 +14,41 @@
 // You should have received a copy of the GNU General Public License
 // along with polkadot-rewards.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::{
-	api::Api,
-	primitives::{CsvRecord, GroupedCsvRecord, Output, SeparatedCsvRecord},
-};
-use anyhow::{anyhow, bail, ensure, Context, Error};
+use anyhow::{ensure, Context, Error};
 use argh::FromArgs;
-use chrono::{naive::NaiveDateTime, NaiveDate};
+use chrono::{naive::NaiveDateTime, NaiveDate, SecondsFormat, Utc};
 use env_logger::{Builder, Env};
 use indicatif::{ProgressBar, ProgressStyle};
-use itertools::Itertools;
-use sp_arithmetic::{FixedPointNumber, FixedU128};
-use std::{collections::HashMap, path::PathBuf, str::FromStr};
+use polkadot_rewards::{
+	api::{Api, ApiConfig, Query},
+	batch,
+	cache::{self, CacheStore, Export},
+	err,
+	fixtures::Fixtures,
+	network::{Network, Registry},
+	price::PriceSource,
+	primitives::{
+		self, CsvRecord, Dialect, Format, GroupedCsvRecord, Metadata, OptionalPrice, Output, Priced, QuoteStyle,
+		SeparatedCsvRecord,
+	},
+	report::PortfolioReport,
+	source::Source,
+};
+use std::{
+	env,
+	fs::File,
+	io::{self, BufReader, BufWriter, Write},
+	path::{Path, PathBuf},
+};
 
 const OUTPUT_DATE: &str = "%Y-%m-%d";
 const OUTPUT_TIME: &str = "%H:%M:%S";
+/// Environment variable with the default of `--subscan-url`.
+const SUBSCAN_URL_VAR: &str = "POLKADOT_REWARDS_SUBSCAN_URL";
+/// Environment variable with the default of `--price-url`.
+const PRICE_URL_VAR: &str = "POLKADOT_REWARDS_PRICE_URL";
 
-#[derive(FromArgs, PartialEq, Eq, Debug)]
+#[derive(FromArgs, PartialEq, Debug)]
 /// Polkadot Staking Rewards CLI-App
 pub struct App {
 	#[argh(option, from_str_fn(date_from_string), short = 'f')]
@@ -39,19 +57,25 @@ pub struct App {
 	/// date to stop crawling for staking rewards. Format: "YYY-MM-DD HH:MM:SS"
 	#[argh(option, from_str_fn(date_from_string), short = 't')]
 	pub to: Option<NaiveDateTime>,
-	/// network to crawl for rewards. One of: [Polkadot, Kusama, Moonriver, Moonbeam, Calamari, MOVR, GLMR, KSM, DOT, KMA]
-	#[argh(option, default = "Network::Polkadot", short = 'n')]
-	pub network: Network,
-	/// the fiat currency which should be used for prices
-	#[argh(option, short = 'c')]
+	/// network to crawl for rewards. One of: [Polkadot, Kusama, Moonriver, Moonbeam, Astar, Calamari, Aleph, MOVR, GLMR, KSM, DOT, ASTR, KMA, AZERO], or a network from `--networks`. Inferred from the address by default.
+	#[argh(option, short = 'n')]
+	pub network: Option<String>,
+	/// TOML file with additional networks, or overrides of the built-in ones.
+	#[argh(option)]
+	pub networks: Option<PathBuf>,
+	/// the fiat currency which should be used for prices. Default: usd
+	#[argh(option, short = 'c', default = "String::from(\"usd\")")]
 	pub currency: String,
-	/// network-formatted address to get staking rewards for.
+	/// address to get staking rewards for. Either network-formatted, generic (prefix 42), or a hex public key.
 	#[argh(option, short = 'a')]
-	pub address: String,
+	pub address: Option<String>,
+	/// CSV or TOML file listing many accounts to get staking rewards for, with `address`, `network` and `label` columns.
+	#[argh(option)]
+	pub addresses_file: Option<PathBuf>,
 	/// change the user agent for HTTPS requests
 	#[argh(option, short = 'u', default = "default_user_agent()")]
 	pub user: String,
-	/// date format to use in output CSV data. Uses rfc2822 by default.  EX: "%Y-%m-%d %H:%M:%S".
+	/// date format to use in output CSV data. Default "%Y-%m-%d". EX: "%Y-%m-%d %H:%M:%S".
 	#[argh(option, default = "OUTPUT_DATE.to_string()")]
 	pub date_format: String,
 	#[argh(option, default = "OUTPUT_TIME.to_string()")]
@@ -69,15 +93,169 @@ pub struct App {
 	#[argh(switch)]
 	/// do not group blocks by day. Give each block its own column. Adds additional exact UTC `time` column.
 	pub no_group: bool,
+	/// round amounts to this many fractional digits. Amounts are exact by default.
+	#[argh(option)]
+	pub precision: Option<u8>,
 	#[argh(switch)]
 	/// preview the rewards in your terminal instead of outputting CSV format.
 	pub preview: bool,
+	/// format of the rewards of each account. One of: [csv, json, ndjson, koinly]. JSON has typed fields, ISO dates and the network, address and currency. Koinly is the universal CSV template of Koinly. Portfolio files are always CSV. Default: csv
+	#[argh(option, default = "Format::Csv")]
+	pub format: Format,
+	/// character which separates the columns of CSV output, or `tab`. Default: ;
+	#[argh(option, from_str_fn(delimiter_from_string), default = "b';'")]
+	pub delimiter: u8,
+	/// do not start CSV output with a row of column names.
+	#[argh(switch)]
+	pub no_header: bool,
+	/// when to quote the fields of CSV output. One of: [always, necessary, non-numeric, never]. Default: necessary
+	#[argh(option, default = "QuoteStyle::Necessary")]
+	pub quote_style: QuoteStyle,
+	/// write amounts and prices with a decimal comma, e.g. `1,5` instead of `1.5`. Only affects CSV output and previews.
+	#[argh(switch)]
+	pub decimal_comma: bool,
 	/// get extra information about the program execution.
 	#[argh(switch, short = 'v')]
 	pub verbose: bool,
 	/// the subscan api key, optionally provided.
-	#[argh(option, short = 'k')]
+	#[argh(option, short = 'k', default = "String::new()")]
 	pub api_key: String,
+	/// where to read rewards from. One of: [subscan, file:<path-to-json>]. Default: subscan
+	#[argh(option, default = "Source::Subscan")]
+	pub source: Source,
+	/// where to get historical prices from. One of: [coingecko, cryptocompare, subscan]. Default: coingecko
+	#[argh(option, default = "PriceSource::CoinGecko")]
+	pub price_source: PriceSource,
+	/// how often to attempt a request which failed because of rate limits or server errors. Default: 5
+	#[argh(option, default = "5")]
+	pub max_attempts: u32,
+	/// requests per second to send to Subscan. Default: 3, or 5 with an API key. Raise it for paid Subscan plans.
+	#[argh(option)]
+	pub subscan_rps: Option<f64>,
+	/// requests per minute to send to the price source. Default: 12 for coingecko, 240 for cryptocompare, the Subscan rate for subscan.
+	#[argh(option)]
+	pub price_rpm: Option<f64>,
+	/// how many requests to send at once. They still keep to the rate limits. Default: 4
+	#[argh(option, default = "4")]
+	pub jobs: usize,
+	/// write every response to a fixture file in this directory, to replay it later with `--replay`.
+	#[argh(option)]
+	pub record: Option<PathBuf>,
+	/// read responses from the fixture files in this directory instead of sending requests.
+	#[argh(option)]
+	pub replay: Option<PathBuf>,
+	/// base URL of a Subscan-compatible API. `{{network}}` is replaced by the name of the network, e.g. http://127.0.0.1:8080/{{network}}.api.subscan.io/api. Default: $POLKADOT_REWARDS_SUBSCAN_URL, or the URL of the network
+	#[argh(option)]
+	pub subscan_url: Option<String>,
+	/// base URL of an API compatible with the price source. Default: $POLKADOT_REWARDS_PRICE_URL, or the public API of the price source
+	#[argh(option)]
+	pub price_url: Option<String>,
+	/// directory of the local cache. Default: $XDG_CACHE_HOME/polkadot-rewards, or ./cache_store
+	#[argh(option)]
+	pub cache_dir: Option<PathBuf>,
+	#[argh(subcommand)]
+	pub command: Option<Command>,
+}
+
+impl App {
+	/// How CSV output is written.
+	pub fn dialect(&self) -> Dialect {
+		Dialect { delimiter: self.delimiter, header: !self.no_header, quote_style: self.quote_style }
+	}
+
+	/// Settings of the API.
+	pub fn config(&self) -> ApiConfig {
+		ApiConfig {
+			user_agent: self.user.clone(),
+			api_key: self.api_key.clone(),
+			cache_dir: self.cache_dir.clone().unwrap_or_else(cache::default_dir),
+			currency: self.currency.clone(),
+			source: self.source.clone(),
+			price_source: self.price_source,
+			subscan_rps: self.subscan_rps,
+			price_rpm: self.price_rpm,
+			max_attempts: self.max_attempts,
+			jobs: self.jobs,
+			fixtures: match (&self.record, &self.replay) {
+				(_, Some(dir)) => Some(Fixtures::Replay(dir.clone())),
+				(Some(dir), None) => Some(Fixtures::Record(dir.clone())),
+				(None, None) => None,
+			},
+			subscan_url: self.subscan_url.clone().or_else(|| env::var(SUBSCAN_URL_VAR).ok().filter(|u| !u.is_empty())),
+			price_url: self.price_url.clone().or_else(|| env::var(PRICE_URL_VAR).ok().filter(|u| !u.is_empty())),
+		}
+	}
+}
+
+#[derive(FromArgs, PartialEq, Eq, Debug)]
+#[argh(subcommand)]
+pub enum Command {
+	Cache(CacheCommand),
+}
+
+#[derive(FromArgs, PartialEq, Eq, Debug)]
+/// inspect and manage the local cache of prices, token metadata and rewards.
+#[argh(subcommand, name = "cache")]
+pub struct CacheCommand {
+	#[argh(subcommand)]
+	pub action: CacheAction,
+}
+
+#[derive(FromArgs, PartialEq, Eq, Debug)]
+#[argh(subcommand)]
+pub enum CacheAction {
+	Stats(CacheStats),
+	List(CacheList),
+	Purge(CachePurge),
+	Export(CacheExport),
+	Import(CacheImport),
+}
+
+#[derive(FromArgs, PartialEq, Eq, Debug)]
+/// show the location, size and number of entries of the cache.
+#[argh(subcommand, name = "stats")]
+pub struct CacheStats {}
+
+#[derive(FromArgs, PartialEq, Eq, Debug)]
+/// list the keys of cached entries.
+#[argh(subcommand, name = "list")]
+pub struct CacheList {
+	/// only list entries of this bucket. One of: [prices, metadata, rewards, sync]
+	#[argh(option)]
+	pub bucket: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Eq, Debug)]
+/// remove cached entries. Removes everything if no filter is given.
+#[argh(subcommand, name = "purge")]
+pub struct CachePurge {
+	/// only remove entries of this network.
+	#[argh(option, short = 'n')]
+	pub network: Option<String>,
+	/// only remove prices and rewards of days before this date. Format: "YYYY-MM-DD"
+	#[argh(option, from_str_fn(day_from_string))]
+	pub before: Option<NaiveDate>,
+}
+
+#[derive(FromArgs, PartialEq, Eq, Debug)]
+/// export cached entries as JSON.
+#[argh(subcommand, name = "export")]
+pub struct CacheExport {
+	/// only export entries of this bucket. One of: [prices, metadata, rewards, sync]
+	#[argh(option)]
+	pub bucket: Option<String>,
+	/// file to write the export to. Writes to STDOUT by default.
+	#[argh(option, short = 'o')]
+	pub output: Option<PathBuf>,
+}
+
+#[derive(FromArgs, PartialEq, Eq, Debug)]
+/// import entries from a JSON export, replacing cached entries with the same key.
+#[argh(subcommand, name = "import")]
+pub struct CacheImport {
+	/// JSON file created by `cache export`.
+	#[argh(positional)]
+	pub input: PathBuf,
 }
 
 fn default_user_agent() -> String {
@@ -105,164 +283,257 @@ pub fn date_from_string(value: &str) -> Result<chrono::NaiveDateTime, String> {
 	Ok(time)
 }
 
-#[derive(PartialEq, Eq, Debug)]
-pub enum Network {
-	/// The Polkadot Network
-	Polkadot,
-	/// The Kusama Network
-	Kusama,
-	/// The Moonriver Network
-	Moonriver,
-	/// The Moonbeam Network
-	Moonbeam,
-	/// The Astar Network
-	Astar,
-	/// The Calamari Network
-	Calamari,
-	/// Alepgh-zero solo-chain
-	Aleph,
+pub fn delimiter_from_string(value: &str) -> Result<u8, String> {
+	match value {
+		"tab" | "\\t" => Ok(b'\t'),
+		_ if value.len() == 1 && value.is_ascii() && !matches!(value, "\"" | "\n" | "\r") => Ok(value.as_bytes()[0]),
+		_ => Err(format!("Delimiter must be a single ASCII character other than a quote or line break, got '{value}'")),
+	}
 }
 
-impl Network {
-	pub fn id(&self) -> &'static str {
-		match self {
-			Self::Polkadot => "polkadot",
-			Self::Kusama => "kusama",
-			Self::Moonbeam => "moonbeam",
-			Self::Moonriver => "moonriver",
-			Self::Astar => "astar",
-			Self::Calamari => "calamari",
-			Self::Aleph => "aleph",
-		}
+pub fn day_from_string(value: &str) -> Result<NaiveDate, String> {
+	NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|e| e.to_string())
+}
+
+pub fn app() -> Result<(), Error> {
+	let app: App = argh::from_env();
+	if app.verbose {
+		Builder::from_env(Env::default().default_filter_or("info")).init();
+	}
+	ensure!(app.record.is_none() || app.replay.is_none(), "`--record` can not be used together with `--replay`.");
+	let registry = Registry::load(app.networks.as_deref())?;
+	if let Some(Command::Cache(command)) = &app.command {
+		return cache_command(&app, &registry, &command.action);
 	}
+	let progress = if app.verbose { None } else { Some(construct_progress_bar()?) };
 
-	fn amount_to_network(&self, amount: &u128) -> Result<f64, Error> {
-		// TODO: fetch this from the metadata
-		let denominator = match self {
-			Self::Polkadot => 10u128.pow(10),
-			Self::Kusama => 10u128.pow(12),
-			Self::Moonriver => 10u128.pow(18),
-			Self::Moonbeam => 10u128.pow(18),
-			Self::Astar => 10u128.pow(18),
-			Self::Calamari => 10u128.pow(12),
-			Self::Aleph => 10u128.pow(12),
-		};
-		let frac = FixedU128::checked_from_rational(*amount, denominator)
-			.ok_or_else(|| anyhow!("Amount '{}' overflowed FixedU128", amount))?
-			.to_float();
-		Ok(frac)
+	if let Some(addresses_file) = &app.addresses_file {
+		return batch(&app, &registry, addresses_file, progress.as_ref());
 	}
+
+	let address = app.address.as_deref().context("One of `--address` or `--addresses-file` is required.")?;
+	let query = Query::resolve(&registry, app.network.as_deref(), address)?.range(app.from, app.to);
+	let api = Api::new(app.config())?.with_progress(progress.as_ref());
+	let rewards = create_rewards(&api, &query, &app)?;
+	if rewards.is_empty() {
+		return Err(err::Error::NoRewards(format!("account {}", query.address)).into());
+	}
+	output(&app, &query, &api.network(&query)?, &rewards, progress.as_ref())
 }
 
-impl FromStr for Network {
-	type Err = Error;
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		match s.to_lowercase().as_str() {
-			"polkadot" | "dot" => Ok(Network::Polkadot),
-			"kusama" | "ksm" => Ok(Network::Kusama),
-			"moonriver" | "movr" => Ok(Network::Moonriver),
-			"moonbeam" | "glmr" => Ok(Network::Moonbeam),
-			"astar" | "astr" => Ok(Network::Astar),
-			"calamari" | "kma" => Ok(Network::Calamari),
-			"aleph" | "aleph-zero" | "azero" => Ok(Network::Aleph),
-			_ => bail!( "Network must be one of: 'kusama', 'polkadot', 'moonbeam', 'astar', 'moonriver', 'aleph-zoer', or their token abbreviations."),
+/// Crawl the rewards of every account in `addresses_file`.
+///
+/// Writes one output per account, and a portfolio file with the rewards of all accounts.
+fn batch(app: &App, registry: &Registry, addresses_file: &Path, progress: Option<&ProgressBar>) -> Result<(), Error> {
+	ensure!(!app.stdout, "`--stdout` can not be used together with `--addresses-file`.");
+	let accounts = batch::load(addresses_file)?;
+	// check all addresses before starting to crawl
+	let queries = accounts
+		.iter()
+		.map(|account| {
+			let network = account.network.as_deref().or(app.network.as_deref());
+			Ok(Query::resolve(registry, network, &account.address)?.range(app.from, app.to))
+		})
+		.collect::<Result<Vec<_>, Error>>()?;
+
+	let api = Api::new(app.config())?.with_progress(progress);
+	let mut portfolio = Vec::new();
+	let mut report = PortfolioReport::default().with_decimal_comma(app.decimal_comma);
+	for (account, query) in accounts.iter().zip(&queries) {
+		let rewards =
+			create_rewards(&api, query, app).with_context(|| format!("Failed to crawl {}", account.label()))?;
+		if rewards.is_empty() {
+			log::warn!("No rewards found for {}", account.label());
+			continue;
+		}
+		if app.preview {
+			println!("{}", account.label());
 		}
+		let network = api.network(query)?;
+		output(app, query, &network, &rewards, progress)?;
+		portfolio.extend(rewards.to_portfolio(account.label(), &network));
+		report.add(&network, &rewards);
+	}
+	if portfolio.is_empty() {
+		return Err(err::Error::NoRewards(format!("any account in {}", addresses_file.display())).into());
 	}
-}
 
-pub fn app() -> Result<(), Error> {
-	let mut app: App = argh::from_env();
-	let progress = if app.verbose {
-		Builder::from_env(Env::default().default_filter_or("info")).init();
-		None
+	if !app.preview {
+		let stem = addresses_file.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+		let file_name = format!("portfolio->{}-{stem}-rewards", app.currency);
+		let mut path = app.folder.join(&file_name);
+		path.set_extension("csv");
+		let mut wtr = Output::new(app.stdout, &path, &app.dialect()).context("Failed to create output.")?;
+		portfolio.into_iter().try_for_each(|r| wtr.serialize(r).context("Failed to format PortfolioRecord"))?;
+
+		let report_name = format!("portfolio-report->{}-{stem}", app.currency);
+		let mut path = app.folder.join(&report_name);
+		path.set_extension("csv");
+		let mut wtr = Output::new(app.stdout, &path, &app.dialect()).context("Failed to create output.")?;
+		report.serialize(&mut wtr, &app.date_format).context("Failed to format PortfolioReport")?;
+		progress.map(|p| p.finish_with_message(format!("Wrote portfolio to files: {file_name}, {report_name}")));
 	} else {
-		Some(construct_progress_bar()?)
-	};
-	let api = Api::new(&app, progress.as_ref());
+		println!("Portfolio");
+		cli_table::print_stdout(report.with_title(&app.date_format))?;
+	}
+	Ok(())
+}
+
+fn cache_command(app: &App, registry: &Registry, action: &CacheAction) -> Result<(), Error> {
+	let dir = app.config().cache_dir;
+	let store = CacheStore::open(&dir)?;
+	match action {
+		CacheAction::Stats(_) => {
+			println!("Cache: {}", store.path()?.display());
+			println!("Size on disk: {} bytes", store.size_on_disk()?);
+			for (bucket, entries) in store.stats()? {
+				println!("{bucket}: {entries} entries");
+			}
+		}
+		CacheAction::List(list) => {
+			for (bucket, key) in store.list(list.bucket.as_deref())? {
+				println!("{bucket}: {key}");
+			}
+		}
+		CacheAction::Purge(purge) => {
+			let network = purge.network.as_deref().map(|n| registry.get(n)).transpose()?;
+			let removed = store.purge(network.map(|n| n.id()), purge.before)?;
+			println!("Removed {removed} entries from {}", dir.display());
+		}
+		CacheAction::Export(export) => {
+			let entries = store.export(export.bucket.as_deref())?;
+			let writer: Box<dyn Write> = match &export.output {
+				Some(path) => {
+					Box::new(File::create(path).with_context(|| format!("Failed to create export {}", path.display()))?)
+				}
+				None => Box::new(io::stdout()),
+			};
+			let mut writer = BufWriter::new(writer);
+			serde_json::to_writer_pretty(&mut writer, &entries)?;
+			writeln!(writer)?;
+		}
+		CacheAction::Import(import) => {
+			let file = File::open(&import.input)
+				.with_context(|| format!("Failed to read export {}", import.input.display()))?;
+			let entries: Export = serde_json::from_reader(BufReader::new(file))
+				.with_context(|| format!("Failed to parse export {}", import.input.display()))?;
+			let imported = store.import(entries)?;
+			println!("Imported {imported} entries into {}", dir.display());
+		}
+	}
+	Ok(())
+}
 
-	let rewards = if app.no_group {
-		CsvRecord::Separated(create_separated_rewards(&api, &app)?)
+fn create_rewards(api: &Api, query: &Query, app: &App) -> Result<CsvRecord, Error> {
+	let network = api.network(query)?;
+	// Koinly books every reward on its own, with the extrinsic which paid it out
+	if app.no_group || app.format == Format::Koinly {
+		Ok(CsvRecord::Separated(create_separated_rewards(api, query, &network, app)?))
 	} else {
-		CsvRecord::Grouped(create_grouped_rewards(&api, &app)?)
-	};
+		Ok(CsvRecord::Grouped(create_grouped_rewards(api, query, &network, app)?))
+	}
+}
 
-	let file_name = construct_file_name(&app, rewards.to_date_rev(), rewards.to_date());
-	app.folder.push(&file_name);
-	app.folder.set_extension("csv");
+/// Write the rewards of one account to a file or STDOUT, or preview them in the terminal.
+fn output(
+	app: &App,
+	query: &Query,
+	network: &Network,
+	rewards: &CsvRecord,
+	progress: Option<&ProgressBar>,
+) -> Result<(), Error> {
+	// slashes are kept apart from rewards, so they don't get booked as income.
+	let summary = rewards.summary();
+	log::info!("{}: {summary}", query.address);
 
 	if !app.preview {
-		let mut wtr = Output::new(&app).context("Failed to create output.")?;
-		rewards.serialize(&mut wtr)?;
+		let file_name = construct_file_name(app, query, rewards.to_date_rev(), rewards.to_date());
+		let mut path = app.folder.join(&file_name);
+		path.set_extension(app.format.extension());
+
+		match app.format {
+			Format::Csv => {
+				let mut wtr = Output::new(app.stdout, &path, &app.dialect()).context("Failed to create output.")?;
+				rewards.serialize(&mut wtr)?;
+			}
+			Format::Koinly => {
+				let mut wtr = Output::new(app.stdout, &path, &Dialect::koinly()).context("Failed to create output.")?;
+				rewards
+					.to_koinly(network, &app.currency)
+					.into_iter()
+					.try_for_each(|r| wtr.serialize(r).context("Failed to format KoinlyRecord"))?;
+			}
+			Format::Json | Format::Ndjson => {
+				let metadata = Metadata {
+					network: network.id().to_string(),
+					address: query.address.clone(),
+					token: network.symbol.clone(),
+					currency: app.currency.to_lowercase(),
+					from: query.from,
+					to: query.to,
+					generated_at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+				};
+				let writer = primitives::writer(app.stdout, &path).context("Failed to create output.")?;
+				rewards.write_json(writer, &metadata, app.format)?;
+			}
+		}
 		if app.stdout {
-			progress.map(|p| p.finish_with_message("Writing data to STDOUT"));
+			progress.map(|p| p.finish_with_message(format!("Writing data to STDOUT. {summary}")));
 		} else {
-			progress.map(move |p| p.finish_with_message(format!("Wrote data to file: {file_name}")));
+			progress.map(|p| p.finish_with_message(format!("Wrote data to file: {file_name}. {summary}")));
 		}
 	} else {
 		cli_table::print_stdout(rewards.with_title())?;
-		progress.as_ref().map(|p| p.finish_with_message("Wrote preview"));
-		progress.as_ref().map(|p| p.finish_and_clear());
+		println!("{summary}");
+		progress.map(|p| p.finish_with_message("Wrote preview"));
+		progress.map(|p| p.finish_and_clear());
 	}
-
 	Ok(())
 }
 
-fn create_grouped_rewards(api: &Api, app: &App) -> Result<Vec<GroupedCsvRecord>, Error> {
-	let rewards = api.fetch_all_rewards().context("Failed to fetch rewards.")?;
-	let prices = if app.no_price {
-		(0..rewards.len()).map(|_| None).collect::<Vec<Option<_>>>()
-	} else {
-		let dates: Vec<NaiveDate> = rewards.iter().map(|r| r.day).collect();
-		api.fetch_prices(dates.as_slice()).context("Failed to fetch prices.")?.into_iter().map(Some).collect::<Vec<_>>()
-	};
-
-	ensure!(!rewards.is_empty(), "No rewards found for specified account.");
-
-	rewards
-		.iter()
-		.zip(&prices)
-		.map(|(reward, price)| {
-			Ok(GroupedCsvRecord {
-				block_nums: reward.block_nums.iter().fold(String::new(), |acc, i| format!("{acc}+{i}"))[1..]
-					.to_string(),
-				date: reward.day.format(&app.date_format).to_string(),
-				amount: app.network.amount_to_network(&reward.amount)?,
-				price: price.into(),
-			})
+fn create_grouped_rewards(
+	api: &Api,
+	query: &Query,
+	network: &Network,
+	app: &App,
+) -> Result<Vec<GroupedCsvRecord>, Error> {
+	let rewards = api.priced_rewards(query, !app.no_price)?;
+	Ok(rewards
+		.into_iter()
+		.map(|Priced { reward, price }| GroupedCsvRecord {
+			block_nums: reward.block_nums.iter().fold(String::new(), |acc, i| format!("{acc}+{i}"))[1..].to_string(),
+			blocks: reward.block_nums.iter().copied().collect(),
+			day: reward.day,
+			date: reward.day.format(&app.date_format).to_string(),
+			amount: network.amount_to_network(&reward.amount, app.precision).with_decimal_comma(app.decimal_comma),
+			slash: network.amount_to_network(&reward.slash, app.precision).with_decimal_comma(app.decimal_comma),
+			price: OptionalPrice::from(price).with_decimal_comma(app.decimal_comma),
 		})
-		.collect::<Result<_, Error>>()
+		.collect())
 }
 
-fn create_separated_rewards(api: &Api, app: &App) -> Result<Vec<SeparatedCsvRecord>, Error> {
-	let rewards = api.fetch_all_rewards_separated().context("Failed to fetch rewards.")?;
-
-	let dates = rewards.iter().map(|r| r.day).unique().collect::<Vec<NaiveDate>>();
-	let prices: HashMap<NaiveDate, f64> = if app.no_price {
-		HashMap::new()
-	} else {
-		api.fetch_prices(dates.as_slice())
-			.context("Failed to fetch prices.")?
-			.into_iter()
-			.enumerate()
-			.map(|(i, p)| (dates[i], p))
-			.collect()
-	};
-
-	ensure!(!rewards.is_empty(), "No rewards found for specified account.");
-
-	rewards
-		.iter()
-		.map(|r| {
-			let price = prices.get(&r.day);
-			Ok(SeparatedCsvRecord {
-				date: r.day.format(&app.date_format).to_string(),
-				time: r.time.format(&app.time_format).to_string(),
-				block_number: format!("{}", r.block_num),
-				amount: app.network.amount_to_network(&r.amount)?,
-				price: price.copied().into(),
-			})
+fn create_separated_rewards(
+	api: &Api,
+	query: &Query,
+	network: &Network,
+	app: &App,
+) -> Result<Vec<SeparatedCsvRecord>, Error> {
+	let rewards = api.priced_rewards_separated(query, !app.no_price)?;
+	Ok(rewards
+		.into_iter()
+		.map(|Priced { reward, price }| SeparatedCsvRecord {
+			day: reward.day,
+			time_of_day: reward.time,
+			block_num: reward.block_num,
+			extrinsic_hash: reward.extrinsic_hash,
+			date: reward.day.format(&app.date_format).to_string(),
+			time: reward.time.format(&app.time_format).to_string(),
+			block_number: format!("{}", reward.block_num),
+			amount: network.amount_to_network(&reward.amount, app.precision).with_decimal_comma(app.decimal_comma),
+			slash: network.amount_to_network(&reward.slash, app.precision).with_decimal_comma(app.decimal_comma),
+			price: OptionalPrice::from(price).with_decimal_comma(app.decimal_comma),
 		})
-		.collect()
+		.collect())
 }
 
 fn construct_progress_bar() -> Result<ProgressBar, Error> {
@@ -279,7 +550,6 @@ fn construct_progress_bar() -> Result<ProgressBar, Error> {
 }
 
 // constructs a file name in the format: `dot-address-from_date-to_date-rewards.csv`
-fn construct_file_name(app: &App, from: String, to: String) -> String {
-	let App { network, currency, address, .. } = app;
-	format!("{}->{currency}-{address}-{from}--{to}-rewards", network.id())
+fn construct_file_name(app: &App, query: &Query, from: String, to: String) -> String {
+	format!("{}->{}-{}-{from}--{to}-rewards", query.network.id(), app.currency, query.address)
 }
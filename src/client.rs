@@ -0,0 +1,186 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of polkadot-rewards.
+
+// polkadot-rewards is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// polkadot-rewards is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with polkadot-rewards.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Entry point for using polkadot-rewards as a library.
+
+use crate::{
+	api::{Api, ApiConfig, Query},
+	network::{Network, Registry},
+	price::PriceSource,
+	primitives::{Priced, RewardEntry, SeparatedRewardEntry},
+	source::Source,
+};
+use anyhow::{Context, Error};
+use chrono::NaiveDateTime;
+use std::path::PathBuf;
+
+/// Fetches the staking rewards of one account, with their prices.
+///
+/// ```no_run
+/// use polkadot_rewards::RewardsClient;
+///
+/// let client = RewardsClient::builder()
+///     .network("kusama")
+///     .address("HNZata7iMYWmk5RvZRTiAsSDhV8366zq2YGb3tLH5Upf74F")
+///     .currency("eur")
+///     .build()?;
+/// let network = client.network()?;
+/// for priced in client.rewards()? {
+///     let amount = network.amount_to_network(&priced.reward.amount, None);
+///     println!("{}: {amount} at {:?}", priced.reward.day, priced.price);
+/// }
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+/// The [`Api`] of the client can be reused to fetch the rewards of other accounts.
+pub struct RewardsClient {
+	api: Api,
+	query: Query,
+	with_prices: bool,
+}
+
+impl RewardsClient {
+	pub fn builder() -> RewardsClientBuilder {
+		RewardsClientBuilder::default()
+	}
+
+	/// the network rewards are fetched for, with the token symbol and decimals of its chain
+	pub fn network(&self) -> Result<Network, Error> {
+		self.api.network(&self.query)
+	}
+
+	/// the address rewards are fetched for, encoded for the network
+	pub fn address(&self) -> &str {
+		&self.query.address
+	}
+
+	pub fn api(&self) -> &Api {
+		&self.api
+	}
+
+	pub fn query(&self) -> &Query {
+		&self.query
+	}
+
+	/// Rewards joined by day, newest first.
+	pub fn rewards(&self) -> Result<Vec<Priced<RewardEntry>>, Error> {
+		self.api.priced_rewards(&self.query, self.with_prices)
+	}
+
+	/// Every reward on its own, newest first.
+	pub fn rewards_separated(&self) -> Result<Vec<Priced<SeparatedRewardEntry>>, Error> {
+		self.api.priced_rewards_separated(&self.query, self.with_prices)
+	}
+}
+
+/// Configures a [`RewardsClient`]. Only the address is required.
+#[derive(Debug, Clone)]
+pub struct RewardsClientBuilder {
+	config: ApiConfig,
+	registry: Option<Registry>,
+	network: Option<String>,
+	address: Option<String>,
+	from: Option<NaiveDateTime>,
+	to: Option<NaiveDateTime>,
+	with_prices: bool,
+}
+
+impl Default for RewardsClientBuilder {
+	fn default() -> Self {
+		Self {
+			config: ApiConfig::default(),
+			registry: None,
+			network: None,
+			address: None,
+			from: None,
+			to: None,
+			with_prices: true,
+		}
+	}
+}
+
+impl RewardsClientBuilder {
+	/// Name or alias of the network. Inferred from the address if not set.
+	pub fn network(mut self, network: impl Into<String>) -> Self {
+		self.network = Some(network.into());
+		self
+	}
+
+	/// SS58 address, hex public key or H160 address of the account.
+	pub fn address(mut self, address: impl Into<String>) -> Self {
+		self.address = Some(address.into());
+		self
+	}
+
+	/// Only fetch rewards between `from` and `to`.
+	pub fn range(mut self, from: Option<NaiveDateTime>, to: Option<NaiveDateTime>) -> Self {
+		self.from = from;
+		self.to = to;
+		self
+	}
+
+	/// Fiat currency of prices. Default: usd
+	pub fn currency(mut self, currency: impl Into<String>) -> Self {
+		self.config.currency = currency.into();
+		self
+	}
+
+	/// Whether to fetch prices. Default: true
+	pub fn with_prices(mut self, with_prices: bool) -> Self {
+		self.with_prices = with_prices;
+		self
+	}
+
+	pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+		self.config.api_key = api_key.into();
+		self
+	}
+
+	pub fn price_source(mut self, price_source: PriceSource) -> Self {
+		self.config.price_source = price_source;
+		self
+	}
+
+	pub fn source(mut self, source: Source) -> Self {
+		self.config.source = source;
+		self
+	}
+
+	pub fn cache_dir(mut self, cache_dir: impl Into<PathBuf>) -> Self {
+		self.config.cache_dir = cache_dir.into();
+		self
+	}
+
+	/// Networks to choose from. Default: the built-in networks
+	pub fn registry(mut self, registry: Registry) -> Self {
+		self.registry = Some(registry);
+		self
+	}
+
+	/// Replace all settings of the API at once.
+	pub fn config(mut self, config: ApiConfig) -> Self {
+		self.config = config;
+		self
+	}
+
+	/// Check the address and look up the network.
+	pub fn build(self) -> Result<RewardsClient, Error> {
+		let address = self.address.context("An address is required.")?;
+		let registry = self.registry.unwrap_or_else(Registry::builtin);
+		let query = Query::resolve(&registry, self.network.as_deref(), &address)?.range(self.from, self.to);
+		Ok(RewardsClient { api: Api::new(self.config)?, query, with_prices: self.with_prices })
+	}
+}
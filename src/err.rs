@@ -0,0 +1,119 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of polkadot-rewards.
+
+// polkadot-rewards is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// polkadot-rewards is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with polkadot-rewards.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Errors callers may want to tell apart, and the exit codes of the process.
+//!
+//! | code | meaning                                                      |
+//! |------|--------------------------------------------------------------|
+//! | 0    | success                                                      |
+//! | 1    | any other error, e.g. invalid arguments                      |
+//! | 2    | no rewards found for the account(s) in the range             |
+//! | 3    | a request failed, or a server could not be reached           |
+//! | 4    | Subscan answered with an error code                          |
+//! | 5    | the fiat currency is not supported by the price source       |
+//! | 6    | a response could not be decoded                              |
+//! | 7    | a file could not be read or written, or a fixture is missing |
+
+use std::{io, path::PathBuf};
+
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_NO_REWARDS: i32 = 2;
+pub const EXIT_HTTP: i32 = 3;
+pub const EXIT_API: i32 = 4;
+pub const EXIT_UNSUPPORTED_CURRENCY: i32 = 5;
+pub const EXIT_DECODE: i32 = 6;
+pub const EXIT_IO: i32 = 7;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+	/// A request failed with an HTTP error status, or the server could not be reached.
+	#[error("Request to {url} failed after {attempts} attempt(s): {source}")]
+	Http { url: String, attempts: u32, source: Box<dyn std::error::Error + Send + Sync> },
+	/// Subscan answered with a non-zero `code`.
+	#[error("Subscan returned error {code}: {message}{}", kind.hint())]
+	Api { kind: ApiErrorKind, code: usize, message: String },
+	#[error("Fiat currency '{currency}' is not supported by {provider}. Supported: {supported}")]
+	UnsupportedCurrency { currency: String, provider: &'static str, supported: String },
+	#[error("Failed to decode response: {body}")]
+	Decode { body: String, source: serde_json::Error },
+	#[error("No rewards found for {0}")]
+	NoRewards(String),
+	#[error("Failed to access {}", path.display())]
+	Io { path: PathBuf, source: io::Error },
+	/// A request was not recorded in the fixtures being replayed.
+	#[error("No fixture of {method} {url} in {}. Record it with `--record`", path.display())]
+	MissingFixture { method: String, url: String, path: PathBuf },
+}
+
+/// Subscan `code` of a record which does not exist, e.g. an unknown address.
+const SUBSCAN_RECORD_NOT_FOUND: usize = 10004;
+/// Subscan `code` of a request exceeding the rate limit of the API key.
+const SUBSCAN_RATE_LIMITED: usize = 20008;
+
+/// Known errors Subscan answers with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiErrorKind {
+	InvalidKey,
+	RateLimited,
+	AddressNotFound,
+	Other,
+}
+
+impl ApiErrorKind {
+	/// Tell errors apart by `code`, or by `message` for errors whose code is not documented.
+	pub fn new(code: usize, message: &str) -> Self {
+		let message = message.to_lowercase();
+		match code {
+			SUBSCAN_RATE_LIMITED => ApiErrorKind::RateLimited,
+			SUBSCAN_RECORD_NOT_FOUND => ApiErrorKind::AddressNotFound,
+			_ if message.contains("api key") || message.contains("apikey") => ApiErrorKind::InvalidKey,
+			_ if message.contains("rate limit") => ApiErrorKind::RateLimited,
+			_ if message.contains("address") || message.contains("account") => ApiErrorKind::AddressNotFound,
+			_ => ApiErrorKind::Other,
+		}
+	}
+
+	fn hint(&self) -> &'static str {
+		match self {
+			ApiErrorKind::InvalidKey => ". Check the `--api-key`",
+			ApiErrorKind::RateLimited => ". Lower `--subscan-rps`, or use an API key with a higher limit",
+			ApiErrorKind::AddressNotFound => ". Check that the address belongs to the `--network`",
+			ApiErrorKind::Other => "",
+		}
+	}
+}
+
+impl Error {
+	pub fn exit_code(&self) -> i32 {
+		match self {
+			Error::Http { .. } => EXIT_HTTP,
+			Error::Api { .. } => EXIT_API,
+			Error::UnsupportedCurrency { .. } => EXIT_UNSUPPORTED_CURRENCY,
+			Error::Decode { .. } => EXIT_DECODE,
+			Error::NoRewards(_) => EXIT_NO_REWARDS,
+			Error::Io { .. } | Error::MissingFixture { .. } => EXIT_IO,
+		}
+	}
+}
+
+/// The exit code for an error which ended the program, decided by the first typed error in its chain.
+pub fn exit_code(error: &anyhow::Error) -> i32 {
+	error
+		.chain()
+		.find_map(|e| e.downcast_ref::<Error>().map(Error::exit_code))
+		.or_else(|| error.chain().any(|e| e.is::<io::Error>()).then_some(EXIT_IO))
+		.unwrap_or(EXIT_FAILURE)
+}
@@ -0,0 +1,149 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of polkadot-rewards.
+
+// polkadot-rewards is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// polkadot-rewards is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with polkadot-rewards.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Recorded HTTP responses, to run without network access.
+//!
+//! With [`Fixtures::Record`] every response is written to a file in the directory, named after the request. With
+//! [`Fixtures::Replay`] responses are read from those files instead of being requested, so runs are deterministic and
+//! work offline. API keys are never recorded.
+
+use crate::err;
+use blake2::{Blake2b512, Digest};
+use serde::{Deserialize, Serialize};
+use std::{
+	fs,
+	path::{Path, PathBuf},
+};
+
+/// Where responses are recorded to, or replayed from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fixtures {
+	/// Send requests, and write their responses to the directory.
+	Record(PathBuf),
+	/// Read responses from the directory instead of sending requests.
+	Replay(PathBuf),
+}
+
+/// A request and its response, as stored in a fixture file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Fixture {
+	pub method: String,
+	pub url: String,
+	/// JSON body of the request, if any
+	pub request: Option<serde_json::Value>,
+	pub status: u16,
+	/// the body of the response. Stored as JSON if it is valid JSON, as a string otherwise.
+	pub response: serde_json::Value,
+}
+
+impl Fixture {
+	/// The body of the response, as it was received.
+	pub fn body(&self) -> String {
+		match &self.response {
+			serde_json::Value::String(body) => body.clone(),
+			response => response.to_string(),
+		}
+	}
+}
+
+/// Read all fixtures in `dir`, ordered by file name.
+pub fn load(dir: &Path) -> Result<Vec<Fixture>, err::Error> {
+	let io = |source| err::Error::Io { path: dir.to_path_buf(), source };
+	let mut paths = fs::read_dir(dir)
+		.map_err(io)?
+		.map(|entry| entry.map(|e| e.path()))
+		.collect::<Result<Vec<_>, _>>()
+		.map_err(io)?;
+	paths.retain(|p| p.extension().is_some_and(|e| e == "json"));
+	paths.sort();
+	paths.iter().map(|path| read(path)).collect()
+}
+
+fn read(path: &Path) -> Result<Fixture, err::Error> {
+	let json = fs::read_to_string(path).map_err(|source| err::Error::Io { path: path.to_path_buf(), source })?;
+	serde_json::from_str(&json).map_err(|source| err::Error::Decode { body: json, source })
+}
+
+impl Fixtures {
+	pub fn dir(&self) -> &PathBuf {
+		match self {
+			Fixtures::Record(dir) | Fixtures::Replay(dir) => dir,
+		}
+	}
+
+	/// The file of a request: `{url}-{hash}.json`, where the hash covers the method, the URL and the body.
+	///
+	/// The hash is the first 8 bytes of the blake2b-512 hash of `"{method} {url}\n{body}"`, with the body as compact
+	/// JSON with sorted keys, or empty.
+	fn path(&self, method: &str, url: &str, request: Option<&serde_json::Value>) -> PathBuf {
+		let body = request.map(|r| r.to_string()).unwrap_or_default();
+		let mut hasher = Blake2b512::new();
+		hasher.update(format!("{method} {url}\n{body}"));
+		let hash = hex::encode(&hasher.finalize()[..8]);
+		let name = url
+			.split("://")
+			.last()
+			.unwrap_or(url)
+			.split('?')
+			.next()
+			.unwrap_or_default()
+			.chars()
+			.map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+			.collect::<String>();
+		self.dir().join(format!("{}-{hash}.json", name.trim_matches('_')))
+	}
+
+	/// Write the response to a request.
+	pub(crate) fn record(
+		&self,
+		method: &str,
+		url: &str,
+		request: Option<&serde_json::Value>,
+		status: u16,
+		body: &str,
+	) -> Result<(), err::Error> {
+		let path = self.path(method, url, request);
+		let fixture = Fixture {
+			method: method.to_string(),
+			url: url.to_string(),
+			request: request.cloned(),
+			status,
+			response: serde_json::from_str(body).unwrap_or_else(|_| serde_json::Value::String(body.to_string())),
+		};
+		let json = serde_json::to_string_pretty(&fixture).expect("Fixtures are always serializable");
+		fs::create_dir_all(self.dir())
+			.and_then(|_| fs::write(&path, json + "\n"))
+			.map_err(|source| err::Error::Io { path: path.clone(), source })?;
+		log::debug!("Recorded {method} {url} to {}", path.display());
+		Ok(())
+	}
+
+	/// Read the status and body of the response to a request.
+	pub(crate) fn replay(
+		&self,
+		method: &str,
+		url: &str,
+		request: Option<&serde_json::Value>,
+	) -> Result<(u16, String), err::Error> {
+		let path = self.path(method, url, request);
+		if !path.exists() {
+			return Err(err::Error::MissingFixture { method: method.to_string(), url: url.to_string(), path });
+		}
+		let fixture = read(&path)?;
+		log::debug!("Replaying {method} {url} from {}", path.display());
+		Ok((fixture.status, fixture.body()))
+	}
+}
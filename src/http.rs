@@ -0,0 +1,366 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of polkadot-rewards.
+
+// polkadot-rewards is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// polkadot-rewards is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with polkadot-rewards.  If not, see <http://www.gnu.org/licenses/>.
+
+//! HTTP client shared by all reward sources and price providers.
+
+use crate::{err, fixtures::Fixtures, primitives::ApiResponse};
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use std::{
+	collections::HashMap,
+	sync::{Arc, Mutex},
+	thread,
+	time::{Duration, Instant},
+};
+use ureq::{ErrorKind, Response};
+
+/// How often, and how long to wait before, a failed request is retried.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+	/// number of attempts before giving up, including the first one.
+	pub max_attempts: u32,
+	/// delay before the first retry. Doubled with every further retry.
+	pub base_delay: Duration,
+	/// upper bound of the delay between two attempts. Requests the server asks to retry later than this are not
+	/// retried at all.
+	pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+	pub fn new(max_attempts: u32) -> Self {
+		Self {
+			max_attempts: max_attempts.max(1),
+			base_delay: Duration::from_secs(1),
+			max_delay: Duration::from_secs(60),
+		}
+	}
+
+	/// Exponential backoff with full jitter: a random delay between zero and `base_delay * 2^retry`.
+	fn backoff(&self, retry: u32) -> Duration {
+		let delay = self.base_delay.saturating_mul(2u32.saturating_pow(retry)).min(self.max_delay);
+		delay.mul_f64(fastrand::f64())
+	}
+}
+
+/// How many requests may be sent to a host.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+	per_second: f64,
+}
+
+impl Rate {
+	pub fn per_second(requests: f64) -> Self {
+		Self { per_second: requests }
+	}
+
+	pub fn per_minute(requests: f64) -> Self {
+		Self { per_second: requests / 60.0 }
+	}
+
+	pub fn min(self, other: Rate) -> Rate {
+		Rate { per_second: self.per_second.min(other.per_second) }
+	}
+}
+
+/// Token bucket of a single host.
+///
+/// Holds up to one second worth of requests, so short bursts are allowed while the average stays within the rate.
+#[derive(Debug)]
+struct TokenBucket {
+	rate: Rate,
+	tokens: f64,
+	updated: Instant,
+}
+
+impl TokenBucket {
+	fn new(rate: Rate) -> Self {
+		Self { rate, tokens: 1.0, updated: Instant::now() }
+	}
+
+	fn capacity(&self) -> f64 {
+		self.rate.per_second.max(1.0)
+	}
+
+	/// Take a token, and return how long to wait until it may be used.
+	///
+	/// Tokens may be taken before they are refilled, so waiting callers are served in order.
+	fn take(&mut self) -> Duration {
+		let now = Instant::now();
+		let refilled = now.duration_since(self.updated).as_secs_f64() * self.rate.per_second;
+		self.tokens = (self.tokens + refilled).min(self.capacity()) - 1.0;
+		self.updated = now;
+		if self.tokens >= 0.0 {
+			Duration::ZERO
+		} else {
+			Duration::from_secs_f64(-self.tokens / self.rate.per_second)
+		}
+	}
+}
+
+/// Rate limits of all hosts, shared by every clone of a [`Client`].
+#[derive(Debug, Default)]
+struct RateLimiter {
+	hosts: Mutex<HashMap<String, TokenBucket>>,
+}
+
+impl RateLimiter {
+	/// Block until a request to the host of `url` is allowed.
+	///
+	/// A host requested by clients with different rates is limited to the lowest of them.
+	fn wait(&self, url: &str, rate: Rate) {
+		let host = url.split("://").nth(1).unwrap_or(url).split('/').next().unwrap_or_default();
+		let delay = {
+			let mut hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+			let bucket = hosts.entry(host.to_string()).or_insert_with(|| TokenBucket::new(rate));
+			bucket.rate = bucket.rate.min(rate);
+			bucket.take()
+		};
+		if !delay.is_zero() {
+			log::debug!("Waiting {:.2}s for the rate limit of {host}", delay.as_secs_f64());
+			thread::sleep(delay);
+		}
+	}
+}
+
+/// The final answer to a request: a success, or the error status it still failed with after all attempts.
+struct Reply {
+	status: u16,
+	body: String,
+	attempts: u32,
+}
+
+/// A `ureq` agent which keeps to the rate limit of each host, and retries requests that failed because of rate limits,
+/// server errors or network trouble.
+#[derive(Clone)]
+pub struct Client {
+	agent: ureq::Agent,
+	retry: RetryPolicy,
+	rate: Rate,
+	limiter: Arc<RateLimiter>,
+	fixtures: Option<Fixtures>,
+}
+
+impl Client {
+	pub fn new(agent: ureq::Agent, retry: RetryPolicy, rate: Rate) -> Self {
+		Self { agent, retry, rate, limiter: Arc::default(), fixtures: None }
+	}
+
+	/// Record responses to, or replay them from, `fixtures`.
+	pub fn with_fixtures(mut self, fixtures: Option<Fixtures>) -> Self {
+		self.fixtures = fixtures;
+		self
+	}
+
+	/// A client sending requests at `rate`, sharing the agent and rate limits of this one.
+	pub fn with_rate(&self, rate: Rate) -> Self {
+		Self { rate, ..self.clone() }
+	}
+
+	/// GET a JSON document.
+	pub fn get<T: DeserializeOwned>(&self, url: &str) -> Result<T, err::Error> {
+		decode(self.call("GET", url, None, || self.agent.get(url).call().map_err(Box::new))?)
+	}
+
+	/// POST a JSON body to a Subscan endpoint, and decode the JSON response.
+	pub fn post_json<T: DeserializeOwned>(
+		&self,
+		url: &str,
+		api_key: &str,
+		body: serde_json::Value,
+	) -> Result<T, err::Error> {
+		decode(self.call("POST", url, Some(&body), || {
+			self.agent
+				.post(url)
+				.set("Content-Type", "application/json")
+				.set("X-API-Key", api_key)
+				.send_json(body.clone())
+				.map_err(Box::new)
+		})?)
+	}
+
+	/// Send a request, or replay it from the fixtures. Returns the body of the response.
+	fn call(
+		&self,
+		method: &str,
+		url: &str,
+		body: Option<&serde_json::Value>,
+		request: impl Fn() -> Result<Response, Box<ureq::Error>>,
+	) -> Result<String, err::Error> {
+		let reply = match &self.fixtures {
+			Some(fixtures @ Fixtures::Replay(_)) => {
+				let (status, body) = fixtures.replay(method, url, body)?;
+				Reply { status, body, attempts: 1 }
+			}
+			Some(fixtures @ Fixtures::Record(_)) => {
+				let reply = self.send(url, request)?;
+				fixtures.record(method, url, body, reply.status, &reply.body)?;
+				reply
+			}
+			None => self.send(url, request)?,
+		};
+		if (200..300).contains(&reply.status) {
+			Ok(reply.body)
+		} else {
+			Err(failed(url, reply))
+		}
+	}
+
+	/// Send a request, retrying it if it failed with an error worth retrying.
+	fn send(&self, url: &str, request: impl Fn() -> Result<Response, Box<ureq::Error>>) -> Result<Reply, err::Error> {
+		let mut attempts = 1;
+		loop {
+			self.limiter.wait(url, self.rate);
+			let err = match request() {
+				Ok(response) => {
+					let status = response.status();
+					return match response.into_string() {
+						Ok(body) => Ok(Reply { status, body, attempts }),
+						Err(e) => Err(err::Error::Http { url: url.to_string(), attempts, source: Box::new(e) }),
+					};
+				}
+				Err(e) => e,
+			};
+			let delay = match retry_after(&err) {
+				Some(_) if attempts >= self.retry.max_attempts => None,
+				Some(Some(delay)) if delay > self.retry.max_delay => {
+					log::warn!("{url} asked to retry in {}s, which is too long to wait. Giving up", delay.as_secs());
+					None
+				}
+				Some(delay) => Some(delay.unwrap_or_else(|| self.retry.backoff(attempts - 1))),
+				None => None,
+			};
+			let delay = match delay {
+				Some(delay) => delay,
+				None => {
+					return match *err {
+						ureq::Error::Status(status, response) => {
+							let body = response.into_string().unwrap_or_default();
+							Ok(Reply { status, body, attempts })
+						}
+						err => Err(err::Error::Http { url: url.to_string(), attempts, source: Box::new(err) }),
+					}
+				}
+			};
+			log::warn!("Request to {url} failed ({err}). Retrying in {:.1}s", delay.as_secs_f64());
+			thread::sleep(delay);
+			attempts += 1;
+		}
+	}
+}
+
+/// The error of a request which failed with an error status.
+///
+/// Subscan explains some errors, like an invalid API key, with a `code` and `message` in the body of the response.
+fn failed(url: &str, reply: Reply) -> err::Error {
+	let Reply { status, body, attempts } = reply;
+	match serde_json::from_str::<ApiResponse<serde_json::Value>>(&body).map(ApiResponse::consume) {
+		Ok(Err(api_error @ err::Error::Api { .. })) => api_error,
+		_ => {
+			err::Error::Http { url: url.to_string(), attempts, source: format!("status code {status}: {body}").into() }
+		}
+	}
+}
+
+fn decode<T: DeserializeOwned>(body: String) -> Result<T, err::Error> {
+	serde_json::from_str(&body).map_err(|source| err::Error::Decode { body, source })
+}
+
+/// Whether a failed request is worth retrying, and how long the server asked us to wait before doing so.
+///
+/// Returns `None` if the request should not be retried, and `Some(None)` if the server didn't ask for a delay.
+fn retry_after(err: &ureq::Error) -> Option<Option<Duration>> {
+	match err {
+		ureq::Error::Status(code, response) if *code == 429 || (500..600).contains(code) => {
+			Some(response.header("Retry-After").and_then(parse_retry_after))
+		}
+		ureq::Error::Status(..) => None,
+		ureq::Error::Transport(t) => match t.kind() {
+			ErrorKind::Dns | ErrorKind::ConnectionFailed | ErrorKind::Io => Some(None),
+			_ => None,
+		},
+	}
+}
+
+/// `Retry-After` is either a number of seconds, or an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+	if let Ok(seconds) = value.trim().parse::<u64>() {
+		return Some(Duration::from_secs(seconds));
+	}
+	let date = DateTime::parse_from_rfc2822(value.trim()).ok()?;
+	Some((date.with_timezone(&Utc) - Utc::now()).to_std().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::{
+		io::{Read, Write},
+		net::TcpListener,
+	};
+
+	/// Answer every request with `status` and a `Retry-After` of `retry_after`. Returns the URL of the server.
+	fn server(status: u16, retry_after: &'static str) -> String {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://{}/", listener.local_addr().unwrap());
+		thread::spawn(move || {
+			for mut stream in listener.incoming().flatten() {
+				let _ = stream.read(&mut [0; 1024]);
+				let _ = write!(
+					stream,
+					"HTTP/1.1 {status} Too Many Requests\r\nRetry-After: {retry_after}\r\nContent-Length: 0\r\n\
+					 Connection: close\r\n\r\n"
+				);
+			}
+		});
+		url
+	}
+
+	fn client() -> Client {
+		let retry =
+			RetryPolicy { max_attempts: 3, base_delay: Duration::from_millis(1), max_delay: Duration::from_secs(1) };
+		Client::new(ureq::AgentBuilder::new().build(), retry, Rate::per_second(1000.0))
+	}
+
+	fn attempts(result: Result<serde_json::Value, err::Error>) -> u32 {
+		match result {
+			Err(err::Error::Http { attempts, .. }) => attempts,
+			other => panic!("Expected an HTTP error, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn short_retry_afters_are_waited_for() {
+		let started = Instant::now();
+		assert_eq!(attempts(client().get(&server(429, "1"))), 3);
+		assert!(started.elapsed() >= Duration::from_secs(2));
+	}
+
+	#[test]
+	fn long_retry_afters_are_not_waited_for() {
+		let started = Instant::now();
+		assert_eq!(attempts(client().get(&server(429, "86400"))), 1);
+		assert_eq!(attempts(client().get(&server(503, "Wed, 21 Oct 2065 07:28:00 GMT"))), 1);
+		assert!(started.elapsed() < Duration::from_secs(1));
+	}
+
+	#[test]
+	fn retry_after_is_seconds_or_a_date() {
+		assert_eq!(parse_retry_after(" 120 "), Some(Duration::from_secs(120)));
+		assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::ZERO));
+		assert!(parse_retry_after("Wed, 21 Oct 2065 07:28:00 GMT").unwrap() > Duration::from_secs(86400));
+		assert_eq!(parse_retry_after("soon"), None);
+	}
+}
@@ -0,0 +1,37 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of polkadot-rewards.
+
+// polkadot-rewards is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// polkadot-rewards is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with polkadot-rewards.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Staking rewards of Polkadot ecosystem accounts, with historical prices.
+//!
+//! Start with [`RewardsClient`].
+
+#![allow(clippy::option_map_unit_fn)]
+pub mod address;
+pub mod api;
+pub mod batch;
+pub mod cache;
+pub mod client;
+pub mod err;
+pub mod fixtures;
+mod http;
+pub mod network;
+mod pool;
+pub mod price;
+pub mod primitives;
+pub mod report;
+pub mod source;
+
+pub use client::{RewardsClient, RewardsClientBuilder};
@@ -14,12 +14,12 @@
 // You should have received a copy of the GNU General Public License
 // along with polkadot-rewards.  If not, see <http://www.gnu.org/licenses/>.
 #![allow(clippy::option_map_unit_fn)]
-use anyhow::Error;
-mod api;
+use polkadot_rewards::err;
 mod cli;
-mod primitives;
 
-fn main() -> Result<(), Error> {
-	cli::app()?;
-	Ok(())
+fn main() {
+	if let Err(e) = cli::app() {
+		eprintln!("Error: {e:?}");
+		std::process::exit(err::exit_code(&e));
+	}
 }
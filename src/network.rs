@@ -0,0 +1,159 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of polkadot-rewards.
+
+// polkadot-rewards is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// polkadot-rewards is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with polkadot-rewards.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Registry of the networks rewards can be crawled for.
+
+use crate::primitives::Amount;
+use anyhow::{anyhow, Context, Error};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+/// Networks supported without any configuration.
+const BUILTIN_NETWORKS: &str = include_str!("networks.toml");
+
+/// Stands for the name of the network in Subscan URLs given on the command line.
+pub const PLACEHOLDER: &str = "{network}";
+
+/// `url` with exactly one trailing `/`, so endpoints can be appended to it.
+pub fn base_url(url: &str) -> String {
+	format!("{}/", url.trim_end_matches('/'))
+}
+
+/// A network, as described in the network registry.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct Network {
+	/// lowercase name of the network. Used in file names and cache keys.
+	pub name: String,
+	/// alternative names the network may be selected by, e.g. its token symbol.
+	#[serde(default)]
+	pub aliases: Vec<String>,
+	/// ticker symbol of the networks native token
+	pub symbol: String,
+	/// decimals of the networks native token
+	pub decimals: u8,
+	/// SS58 address prefix of the network
+	pub ss58_prefix: u16,
+	/// whether accounts are 20-byte Ethereum-style (H160) addresses instead of SS58 addresses.
+	#[serde(default)]
+	pub h160: bool,
+	/// base URL of the networks Subscan API. A trailing `/` is added when the registry is loaded.
+	pub subscan_url: String,
+	/// id of the networks token at the price provider, e.g. the CoinGecko coin id.
+	pub price_id: String,
+}
+
+impl Network {
+	pub fn id(&self) -> &str {
+		&self.name
+	}
+
+	/// Whether `name` refers to this network, either by its name or by one of its aliases.
+	pub fn is(&self, name: &str) -> bool {
+		self.name.eq_ignore_ascii_case(name) || self.aliases.iter().any(|a| a.eq_ignore_ascii_case(name))
+	}
+
+	/// Convert an amount of planck into the networks token, optionally rounded to `precision` digits.
+	pub fn amount_to_network(&self, amount: &u128, precision: Option<u8>) -> Amount {
+		let amount = Amount::new(*amount, self.decimals);
+		match precision {
+			Some(precision) => amount.round(precision),
+			None => amount,
+		}
+	}
+}
+
+#[derive(Deserialize, Debug)]
+struct RegistryFile {
+	#[serde(default)]
+	network: Vec<Network>,
+}
+
+/// All networks known to the application.
+#[derive(Debug, Clone)]
+pub struct Registry {
+	networks: Vec<Network>,
+}
+
+impl Registry {
+	/// The networks which are built into the application.
+	pub fn builtin() -> Self {
+		let file: RegistryFile = toml::from_str(BUILTIN_NETWORKS).expect("Built-in network registry is valid TOML");
+		Self { networks: file.network }
+	}
+
+	/// Load the built-in networks, extended by the networks in the TOML file at `path`.
+	///
+	/// Networks in the file replace built-in networks of the same name.
+	pub fn load(path: Option<&Path>) -> Result<Self, Error> {
+		let mut registry = Self::builtin();
+		if let Some(path) = path {
+			let contents = fs::read_to_string(path)
+				.with_context(|| format!("Failed to read network registry {}", path.display()))?;
+			let file: RegistryFile = toml::from_str(&contents)
+				.with_context(|| format!("Failed to parse network registry {}", path.display()))?;
+			for mut network in file.network {
+				network.subscan_url = base_url(&network.subscan_url);
+				registry.networks.retain(|n| !n.name.eq_ignore_ascii_case(&network.name));
+				registry.networks.push(network);
+			}
+		}
+		Ok(registry)
+	}
+
+	pub fn iter(&self) -> impl Iterator<Item = &Network> {
+		self.networks.iter()
+	}
+
+	/// Look up a network by name or alias.
+	pub fn get(&self, name: &str) -> Result<&Network, Error> {
+		self.networks.iter().find(|n| n.is(name)).ok_or_else(|| {
+			anyhow!(
+				"Network must be one of: {}, or their token abbreviations.",
+				self.networks.iter().map(|n| format!("'{}'", n.name)).join(", ")
+			)
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::source::get_endpoint;
+	use std::io::Write;
+
+	#[test]
+	fn subscan_urls_get_a_trailing_slash() {
+		let mut file = tempfile::NamedTempFile::new().unwrap();
+		writeln!(
+			file,
+			"[[network]]\nname = \"westend\"\nsymbol = \"WND\"\ndecimals = 12\nss58_prefix = 42\n\
+			 subscan_url = \"https://westend.api.subscan.io/api\"\nprice_id = \"westend\""
+		)
+		.unwrap();
+		let registry = Registry::load(Some(file.path())).unwrap();
+		let westend = registry.get("westend").unwrap();
+		assert_eq!(get_endpoint(westend, "scan/token"), "https://westend.api.subscan.io/api/scan/token");
+		let polkadot = registry.get("polkadot").unwrap();
+		assert_eq!(get_endpoint(polkadot, "scan/token"), "https://polkadot.api.subscan.io/api/scan/token");
+	}
+
+	#[test]
+	fn base_urls_end_in_exactly_one_slash() {
+		assert_eq!(base_url("http://127.0.0.1:8080"), "http://127.0.0.1:8080/");
+		assert_eq!(base_url("http://127.0.0.1:8080//"), "http://127.0.0.1:8080/");
+	}
+}
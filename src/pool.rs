@@ -0,0 +1,69 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of polkadot-rewards.
+
+// polkadot-rewards is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// polkadot-rewards is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with polkadot-rewards.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A small pool of scoped threads, to send requests concurrently.
+//!
+//! Requests still keep to the rate limits of the [`Client`](crate::http::Client) they are sent with, so running more
+//! jobs only hides the latency of each request.
+
+use anyhow::Error;
+use std::{
+	panic,
+	sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+	thread,
+};
+
+/// Apply `f` to every item on up to `jobs` threads, and return the results in the order of `items`.
+///
+/// Stops handing out items once one fails, and returns the error of the first failed item.
+pub fn map<T, R, F>(items: &[T], jobs: usize, f: F) -> Result<Vec<R>, Error>
+where
+	T: Sync,
+	R: Send,
+	F: Fn(&T) -> Result<R, Error> + Sync,
+{
+	if jobs <= 1 || items.len() <= 1 {
+		return items.iter().map(f).collect();
+	}
+	let next = AtomicUsize::new(0);
+	let failed = AtomicBool::new(false);
+	let mut results = thread::scope(|scope| {
+		let workers = (0..jobs.min(items.len()))
+			.map(|_| {
+				scope.spawn(|| {
+					let mut results = Vec::new();
+					while !failed.load(Ordering::Relaxed) {
+						let i = next.fetch_add(1, Ordering::Relaxed);
+						let item = match items.get(i) {
+							Some(item) => item,
+							None => break,
+						};
+						let result = f(item);
+						if result.is_err() {
+							failed.store(true, Ordering::Relaxed);
+						}
+						results.push((i, result));
+					}
+					results
+				})
+			})
+			.collect::<Vec<_>>();
+		workers.into_iter().flat_map(|w| w.join().unwrap_or_else(|e| panic::resume_unwind(e))).collect::<Vec<_>>()
+	});
+	// items are handed out in order, so every item before a failed one has a result
+	results.sort_by_key(|(i, _)| *i);
+	results.into_iter().map(|(_, r)| r).collect()
+}
@@ -0,0 +1,213 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of polkadot-rewards.
+
+// polkadot-rewards is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// polkadot-rewards is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with polkadot-rewards.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Historical price data providers.
+
+use crate::{
+	err,
+	http::{Client, Rate},
+	network::Network,
+	primitives::{ApiResponse, MarketChart, Price, SubscanPrice},
+	source::get_endpoint,
+};
+use anyhow::{anyhow, bail, Context, Error};
+use chrono::NaiveDate;
+use std::{
+	collections::{BTreeMap, HashMap},
+	str::FromStr,
+};
+
+const COINGECKO_ENDPOINT: &str = "https://api.coingecko.com/api/v3";
+const CRYPTOCOMPARE_ENDPOINT: &str = "https://min-api.cryptocompare.com/data";
+const SUBSCAN_PRICE: &str = "open/price";
+
+/// Which service to query for historical prices.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum PriceSource {
+	/// https://www.coingecko.com
+	CoinGecko,
+	/// https://www.cryptocompare.com
+	CryptoCompare,
+	/// Subscans `open/price` endpoint. Only supports USD.
+	Subscan,
+}
+
+impl FromStr for PriceSource {
+	type Err = Error;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"coingecko" => Ok(PriceSource::CoinGecko),
+			"cryptocompare" => Ok(PriceSource::CryptoCompare),
+			"subscan" => Ok(PriceSource::Subscan),
+			_ => bail!("Price source must be one of: 'coingecko', 'cryptocompare', 'subscan'."),
+		}
+	}
+}
+
+impl PriceSource {
+	/// How many requests to send to the source, if `--price-rpm` is not given.
+	///
+	/// Subscan prices share the rate limit of the rewards.
+	pub fn default_rate(&self, subscan: Rate) -> Rate {
+		match self {
+			// coingecko allows 50 requests per minute
+			// it seems to be a bit oversensitive. We therefore restrain ourselves
+			// to 12 requests a minute.
+			PriceSource::CoinGecko => Rate::per_minute(12.0),
+			PriceSource::CryptoCompare => Rate::per_second(4.0),
+			PriceSource::Subscan => subscan,
+		}
+	}
+}
+
+/// Something that knows the historical price of a networks token.
+pub trait PriceProvider: Send + Sync {
+	/// Identifies the provider in the price cache.
+	fn id(&self) -> &'static str;
+
+	/// Get the prices of the networks token on `day`, keyed by lowercase currency.
+	///
+	/// Providers may return more currencies than were asked for, but must include `currency` if they support it.
+	fn prices(&self, network: &Network, day: NaiveDate, currency: &str) -> Result<HashMap<String, f64>, Error>;
+
+	/// Get the prices of the networks token in `currency` on every day from `from` to `to`, both inclusive, with a
+	/// single request.
+	///
+	/// Days without a price are left out. Providers that can't look up ranges return no prices at all, so every day is
+	/// looked up with [`PriceProvider::prices`] instead.
+	fn range(&self, _: &Network, _: NaiveDate, _: NaiveDate, _: &str) -> Result<BTreeMap<NaiveDate, f64>, Error> {
+		Ok(BTreeMap::new())
+	}
+}
+
+/// Prices from CoinGecko. Returns prices for every supported currency at once, or for ranges of days in one currency.
+pub struct CoinGecko {
+	client: Client,
+	endpoint: String,
+}
+
+impl CoinGecko {
+	/// Query a CoinGecko-compatible API at `endpoint`, or the public API if `None`.
+	pub fn new(client: Client, endpoint: Option<&str>) -> Self {
+		Self { client, endpoint: endpoint.unwrap_or(COINGECKO_ENDPOINT).trim_end_matches('/').to_string() }
+	}
+}
+
+impl PriceProvider for CoinGecko {
+	fn id(&self) -> &'static str {
+		"coingecko"
+	}
+
+	fn prices(&self, network: &Network, day: NaiveDate, _: &str) -> Result<HashMap<String, f64>, Error> {
+		let url = format!("{}/coins/{}/history?date={}", self.endpoint, network.price_id, day.format("%d-%m-%Y"));
+		let price: Price = self.client.get(&url)?;
+		Ok(price.market_data.current_price)
+	}
+
+	/// Uses the first price of every day, which is the one at midnight UTC like the prices of single days.
+	fn range(
+		&self,
+		network: &Network,
+		from: NaiveDate,
+		to: NaiveDate,
+		currency: &str,
+	) -> Result<BTreeMap<NaiveDate, f64>, Error> {
+		let start = from.and_hms_opt(0, 0, 0).context("Invalid day")?.timestamp();
+		let end = to.succ_opt().and_then(|d| d.and_hms_opt(0, 0, 0)).context("Invalid day")?.timestamp();
+		let url = format!(
+			"{}/coins/{}/market_chart/range?vs_currency={currency}&from={start}&to={end}",
+			self.endpoint, network.price_id
+		);
+		let chart: MarketChart = self.client.get(&url)?;
+		Ok(chart.opening_prices().into_iter().filter(|(day, _)| (from..=to).contains(day)).collect())
+	}
+}
+
+/// Prices from CryptoCompare, looked up by token symbol.
+pub struct CryptoCompare {
+	client: Client,
+	endpoint: String,
+}
+
+impl CryptoCompare {
+	/// Query a CryptoCompare-compatible API at `endpoint`, or the public API if `None`.
+	pub fn new(client: Client, endpoint: Option<&str>) -> Self {
+		Self { client, endpoint: endpoint.unwrap_or(CRYPTOCOMPARE_ENDPOINT).trim_end_matches('/').to_string() }
+	}
+}
+
+impl PriceProvider for CryptoCompare {
+	fn id(&self) -> &'static str {
+		"cryptocompare"
+	}
+
+	fn prices(&self, network: &Network, day: NaiveDate, currency: &str) -> Result<HashMap<String, f64>, Error> {
+		let timestamp = day.and_hms_opt(0, 0, 0).context("Invalid day")?.timestamp();
+		let symbol = network.symbol.as_str();
+		let url =
+			format!("{}/pricehistorical?fsym={symbol}&tsyms={}&ts={timestamp}", self.endpoint, currency.to_uppercase());
+		// `{"DOT":{"USD":5.23}}` on success, `{"Response":"Error","Message":"..."}` otherwise.
+		let response: serde_json::Value = self.client.get(&url)?;
+		let prices: HashMap<String, f64> = response
+			.get(symbol)
+			.map(|p| serde_json::from_value(p.clone()))
+			.transpose()?
+			.ok_or_else(|| anyhow!("CryptoCompare returned no price for {symbol}: {response}"))?;
+		Ok(prices.into_iter().map(|(currency, price)| (currency.to_lowercase(), price)).collect())
+	}
+}
+
+/// Prices from Subscan, in USD.
+pub struct Subscan {
+	client: Client,
+	api_key: String,
+	/// replaces the Subscan URL of the network, if set
+	endpoint: Option<String>,
+}
+
+impl Subscan {
+	/// Query a Subscan-compatible API at `endpoint`, or the one of the network if `None`.
+	pub fn new(client: Client, api_key: &str, endpoint: Option<&str>) -> Self {
+		let endpoint = endpoint.map(|e| format!("{}/", e.trim_end_matches('/')));
+		Self { client, api_key: api_key.to_string(), endpoint }
+	}
+}
+
+impl PriceProvider for Subscan {
+	fn id(&self) -> &'static str {
+		"subscan"
+	}
+
+	fn prices(&self, network: &Network, day: NaiveDate, currency: &str) -> Result<HashMap<String, f64>, Error> {
+		if !currency.eq_ignore_ascii_case("usd") {
+			return Err(err::Error::UnsupportedCurrency {
+				currency: currency.to_string(),
+				provider: self.id(),
+				supported: "usd".to_string(),
+			}
+			.into());
+		}
+		let timestamp = day.and_hms_opt(0, 0, 0).context("Invalid day")?.timestamp();
+		let url = match &self.endpoint {
+			Some(endpoint) => format!("{endpoint}{SUBSCAN_PRICE}"),
+			None => get_endpoint(network, SUBSCAN_PRICE),
+		};
+		let price: ApiResponse<SubscanPrice> =
+			self.client.post_json(&url, &self.api_key, ureq::json!({ "time": timestamp }))?;
+		let price = price.consume()?.price.parse()?;
+		Ok(HashMap::from([("usd".to_string(), price)]))
+	}
+}
@@ -14,16 +14,20 @@
 // You should have received a copy of the GNU General Public License
 // along with polkadot-rewards.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::cli::App;
-use anyhow::{Context, Error};
-use chrono::{NaiveDate, NaiveTime};
+use crate::{err, network::Network};
+use anyhow::{bail, Context, Error};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
 use cli_table::{Table, TableStruct, WithTitle};
 use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
 use std::{
-	collections::{BTreeSet, HashMap},
+	collections::{BTreeMap, BTreeSet, HashMap},
+	convert::TryInto,
 	fmt,
 	fs::File,
-	io,
+	io::{self, BufWriter, Write},
+	path::Path,
+	str::FromStr,
 };
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -32,12 +36,55 @@ pub struct ApiResponse<T> {
 	message: String,
 	ttl: Option<usize>,
 	generated_at: Option<usize>,
-	data: T,
+	/// `null` if the request failed
+	data: Option<T>,
 }
 
 impl<T> ApiResponse<T> {
-	pub fn consume(self) -> T {
-		self.data
+	/// When Subscan generated the response.
+	pub fn generated_at(&self) -> Option<NaiveDateTime> {
+		NaiveDateTime::from_timestamp_opt(self.generated_at?.try_into().ok()?, 0)
+	}
+
+	/// How long the response may be cached by Subscan, in seconds.
+	pub fn ttl(&self) -> Option<usize> {
+		self.ttl
+	}
+
+	/// The data of a successful response, or the error Subscan answered with.
+	pub fn consume(self) -> Result<T, err::Error> {
+		log::debug!(
+			"Subscan response: code {}, message '{}', generated at {}, ttl {}",
+			self.code,
+			self.message,
+			self.generated_at().map(|t| t.to_string()).unwrap_or_default(),
+			self.ttl().map(|t| format!("{t}s")).unwrap_or_default(),
+		);
+		match self.data {
+			Some(data) if self.code == 0 => Ok(data),
+			None if self.code == 0 => Err(err::Error::Decode {
+				body: format!("code 0, message '{}', data null", self.message),
+				source: serde::de::Error::custom("Subscan answered with success, but without data"),
+			}),
+			_ => Err(err::Error::Api {
+				kind: err::ApiErrorKind::new(self.code, &self.message),
+				code: self.code,
+				message: self.message,
+			}),
+		}
+	}
+
+	/// Like [`ApiResponse::consume`], but a successful response without data is taken as the default, e.g. an empty
+	/// page of a list.
+	pub fn consume_or_default(self) -> Result<T, err::Error>
+	where
+		T: Default,
+	{
+		if self.code == 0 && self.data.is_none() {
+			log::debug!("Subscan answered with success, but without data: '{}'", self.message);
+			return Ok(T::default());
+		}
+		self.consume()
 	}
 }
 
@@ -47,6 +94,12 @@ pub struct List<T> {
 	pub list: Option<Vec<T>>,
 }
 
+impl<T> Default for List<T> {
+	fn default() -> Self {
+		Self { count: 0, list: None }
+	}
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Price {
 	pub market_data: MarketData,
@@ -57,7 +110,51 @@ pub struct MarketData {
 	pub current_price: HashMap<String, f64>,
 }
 
+/// Response of CoinGeckos `market_chart/range` endpoint, in a single currency.
 #[derive(Serialize, Deserialize, Debug)]
+pub struct MarketChart {
+	/// `[unix time in milliseconds, price]`, oldest first. Five-minutely, hourly or daily, depending on the length of
+	/// the range.
+	pub prices: Vec<(i64, f64)>,
+}
+
+impl MarketChart {
+	/// The first price of every UTC day in the chart, the one closest to midnight.
+	pub fn opening_prices(&self) -> BTreeMap<NaiveDate, f64> {
+		let mut days: BTreeMap<NaiveDate, (i64, f64)> = BTreeMap::new();
+		for &(time, price) in &self.prices {
+			if let Some(day) = NaiveDateTime::from_timestamp_millis(time).map(|t| t.date()) {
+				let first = days.entry(day).or_insert((time, price));
+				if time < first.0 {
+					*first = (time, price);
+				}
+			}
+		}
+		days.into_iter().map(|(day, (_, price))| (day, price)).collect()
+	}
+}
+
+/// `data` of Subscans `scan/token` endpoint
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Tokens {
+	/// symbols of the networks tokens. The native token comes first.
+	pub token: Vec<String>,
+	pub detail: HashMap<String, TokenDetail>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TokenDetail {
+	pub symbol: String,
+	pub token_decimals: u8,
+}
+
+/// `data` of Subscans `open/price` endpoint
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SubscanPrice {
+	pub price: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Reward {
 	pub event_index: String,
 	pub block_num: u64,
@@ -71,36 +168,93 @@ pub struct Reward {
 	pub block_timestamp: usize,
 }
 
-#[derive(Debug)]
+impl Reward {
+	/// `reward_slash` returns both rewards and slashes, told apart by their event.
+	pub fn is_slash(&self) -> bool {
+		matches!(self.event_id.as_str(), "Slash" | "Slashed")
+	}
+
+	/// Returns the `(reward, slash)` amounts of this event. One of them is always zero.
+	pub fn amounts(&self) -> Result<(u128, u128), Error> {
+		let amount: u128 = self.amount.parse()?;
+		if self.is_slash() {
+			Ok((0, amount))
+		} else {
+			Ok((amount, 0))
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
 pub struct RewardEntry {
 	pub block_nums: BTreeSet<u64>,
 	pub day: NaiveDate,
+	/// sum of all rewards on `day`
 	pub amount: u128,
+	/// sum of all slashes on `day`
+	pub slash: u128,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SeparatedRewardEntry {
 	pub block_num: u64,
+	/// extrinsic which paid out the reward
+	pub extrinsic_hash: String,
 	pub day: NaiveDate,
 	pub time: NaiveTime,
 	pub amount: u128,
+	pub slash: u128,
+}
+
+/// A reward with the price of the networks token on its day.
+#[derive(Debug, Clone)]
+pub struct Priced<T> {
+	pub reward: T,
+	/// `None` if prices were not fetched.
+	pub price: Option<f64>,
 }
 
 // "block_num,block_time,amount_dot,price_usd,price_time"
 #[derive(Debug, Serialize, Table, Clone)]
 pub struct GroupedCsvRecord {
+	/// the unformatted `date`
+	#[serde(skip)]
+	#[table(skip)]
+	pub day: NaiveDate,
+	/// the unformatted `block_nums`
+	#[serde(skip)]
+	#[table(skip)]
+	pub blocks: Vec<u64>,
 	#[table(title = "Date")]
 	pub date: String,
 	#[table(title = "Blocks")]
 	pub block_nums: String,
 	#[table(title = "Amount")]
-	pub amount: f64,
+	pub amount: Amount,
+	#[table(title = "Slash")]
+	pub slash: Amount,
 	#[table(title = "Price")]
 	pub price: OptionalPrice,
 }
 
 #[derive(Debug, Serialize, Table, Clone)]
 pub struct SeparatedCsvRecord {
+	/// the unformatted `date`
+	#[serde(skip)]
+	#[table(skip)]
+	pub day: NaiveDate,
+	/// the unformatted `time`
+	#[serde(skip)]
+	#[table(skip)]
+	pub time_of_day: NaiveTime,
+	/// the unformatted `block_number`
+	#[serde(skip)]
+	#[table(skip)]
+	pub block_num: u64,
+	/// extrinsic which paid out the reward
+	#[serde(skip)]
+	#[table(skip)]
+	pub extrinsic_hash: String,
 	#[table(title = "Date")]
 	pub date: String,
 	#[table(title = "Time")]
@@ -108,11 +262,221 @@ pub struct SeparatedCsvRecord {
 	#[table(title = "Block")]
 	pub block_number: String,
 	#[table(title = "Amount")]
-	pub amount: f64,
+	pub amount: Amount,
+	#[table(title = "Slash")]
+	pub slash: Amount,
 	#[table(title = "Price")]
 	pub price: OptionalPrice,
 }
 
+/// A [`GroupedCsvRecord`] with typed fields, for JSON output.
+#[derive(Debug, Serialize)]
+struct GroupedJsonRecord<'a> {
+	date: NaiveDate,
+	blocks: &'a [u64],
+	#[serde(serialize_with = "json_number")]
+	amount: Amount,
+	#[serde(serialize_with = "json_number")]
+	slash: Amount,
+	price: Option<f64>,
+}
+
+impl<'a> From<&'a GroupedCsvRecord> for GroupedJsonRecord<'a> {
+	fn from(record: &'a GroupedCsvRecord) -> Self {
+		Self {
+			date: record.day,
+			blocks: &record.blocks,
+			amount: record.amount,
+			slash: record.slash,
+			price: record.price.get(),
+		}
+	}
+}
+
+/// A [`SeparatedCsvRecord`] with typed fields, for JSON output.
+#[derive(Debug, Serialize)]
+struct SeparatedJsonRecord {
+	date: NaiveDate,
+	time: NaiveTime,
+	block: u64,
+	#[serde(serialize_with = "json_number")]
+	amount: Amount,
+	#[serde(serialize_with = "json_number")]
+	slash: Amount,
+	price: Option<f64>,
+}
+
+impl From<&SeparatedCsvRecord> for SeparatedJsonRecord {
+	fn from(record: &SeparatedCsvRecord) -> Self {
+		Self {
+			date: record.day,
+			time: record.time_of_day,
+			block: record.block_num,
+			amount: record.amount,
+			slash: record.slash,
+			price: record.price.get(),
+		}
+	}
+}
+
+/// Describes the rewards of an account in JSON output.
+#[derive(Debug, Clone, Serialize)]
+pub struct Metadata {
+	pub network: String,
+	pub address: String,
+	pub token: String,
+	pub currency: String,
+	/// start of the range rewards were fetched for
+	pub from: Option<NaiveDateTime>,
+	/// end of the range rewards were fetched for
+	pub to: Option<NaiveDateTime>,
+	/// RFC 3339 timestamp of when the output was generated
+	pub generated_at: String,
+}
+
+/// A JSON document with all records and their metadata.
+#[derive(Serialize)]
+struct JsonDocument<'a, T> {
+	#[serde(flatten)]
+	metadata: &'a Metadata,
+	rewards: Vec<T>,
+}
+
+/// A line of NDJSON output: a record, with the metadata repeated so every line stands on its own.
+#[derive(Serialize)]
+struct JsonLine<'a, T> {
+	#[serde(flatten)]
+	metadata: &'a Metadata,
+	#[serde(flatten)]
+	record: T,
+}
+
+/// Write `records` as one pretty JSON document, or as one JSON object per line.
+fn write_json<W, T>(mut writer: W, metadata: &Metadata, records: Vec<T>, format: Format) -> Result<(), Error>
+where
+	W: Write,
+	T: Serialize,
+{
+	if format == Format::Ndjson {
+		for record in records {
+			serde_json::to_writer(&mut writer, &JsonLine { metadata, record })?;
+			writeln!(writer)?;
+		}
+	} else {
+		serde_json::to_writer_pretty(&mut writer, &JsonDocument { metadata, rewards: records })?;
+		writeln!(writer)?;
+	}
+	writer.flush()?;
+	Ok(())
+}
+
+/// Serialize an amount as a JSON number, with all of its digits.
+fn json_number<S: serde::Serializer>(amount: &Amount, serializer: S) -> Result<S::Ok, S::Error> {
+	RawValue::from_string(amount.with_decimal_comma(false).to_string())
+		.map_err(serde::ser::Error::custom)?
+		.serialize(serializer)
+}
+
+/// A record of any account, in the portfolio of a batch run.
+#[derive(Debug, Serialize, Table, Clone)]
+pub struct PortfolioRecord {
+	#[table(title = "Label")]
+	pub label: String,
+	#[table(title = "Network")]
+	pub network: String,
+	#[table(title = "Token")]
+	pub token: String,
+	#[table(title = "Date")]
+	pub date: String,
+	#[table(title = "Blocks")]
+	pub block_nums: String,
+	#[table(title = "Amount")]
+	pub amount: Amount,
+	#[table(title = "Slash")]
+	pub slash: Amount,
+	#[table(title = "Price")]
+	pub price: OptionalPrice,
+}
+
+/// Koinly label of staking rewards.
+const KOINLY_REWARD: &str = "staking";
+/// Koinly label of slashes, which are booked as lost tokens.
+const KOINLY_SLASH: &str = "lost";
+/// Date format of the Koinly universal template.
+const KOINLY_DATE: &str = "%Y-%m-%d %H:%M:%S UTC";
+
+/// A row of Koinly's universal CSV template. Rewards are received, slashes are sent.
+#[derive(Debug, Serialize, Clone)]
+pub struct KoinlyRecord {
+	#[serde(rename = "Date")]
+	pub date: String,
+	#[serde(rename = "Sent Amount")]
+	pub sent_amount: Option<Amount>,
+	#[serde(rename = "Sent Currency")]
+	pub sent_currency: Option<String>,
+	#[serde(rename = "Received Amount")]
+	pub received_amount: Option<Amount>,
+	#[serde(rename = "Received Currency")]
+	pub received_currency: Option<String>,
+	#[serde(rename = "Fee Amount")]
+	pub fee_amount: Option<Amount>,
+	#[serde(rename = "Fee Currency")]
+	pub fee_currency: Option<String>,
+	/// fiat value of the amount. `None` if prices were not gathered.
+	#[serde(rename = "Net Worth Amount")]
+	pub net_worth_amount: Option<f64>,
+	#[serde(rename = "Net Worth Currency")]
+	pub net_worth_currency: Option<String>,
+	#[serde(rename = "Label")]
+	pub label: String,
+	#[serde(rename = "Description")]
+	pub description: String,
+	#[serde(rename = "TxHash")]
+	pub tx_hash: String,
+}
+
+impl KoinlyRecord {
+	/// A row for the reward and a row for the slash of an entry, leaving out the one which is zero.
+	fn new(
+		time: NaiveDateTime,
+		network: &Network,
+		currency: &str,
+		(amount, slash): (Amount, Amount),
+		price: &OptionalPrice,
+		blocks: &str,
+		tx_hash: &str,
+	) -> Vec<KoinlyRecord> {
+		let row = |amount: Amount, label: &str, description: String| {
+			// Koinly only reads amounts with a decimal point
+			let amount = amount.with_decimal_comma(false);
+			let received = label == KOINLY_REWARD;
+			KoinlyRecord {
+				date: time.format(KOINLY_DATE).to_string(),
+				sent_amount: (!received).then_some(amount),
+				sent_currency: (!received).then(|| network.symbol.clone()),
+				received_amount: received.then_some(amount),
+				received_currency: received.then(|| network.symbol.clone()),
+				fee_amount: None,
+				fee_currency: None,
+				// rounded, so float artifacts of the lossy conversion don't end up in the books
+				net_worth_amount: price.get().map(|p| (amount.to_f64() * p * 1e8).round() / 1e8),
+				net_worth_currency: price.get().map(|_| currency.to_uppercase()),
+				label: label.to_string(),
+				description,
+				tx_hash: tx_hash.to_string(),
+			}
+		};
+		let mut rows = Vec::new();
+		if !amount.is_zero() {
+			rows.push(row(amount, KOINLY_REWARD, format!("{} staking reward in block {blocks}", network.id())));
+		}
+		if !slash.is_zero() {
+			rows.push(row(slash, KOINLY_SLASH, format!("{} slash in block {blocks}", network.id())));
+		}
+		rows
+	}
+}
+
 #[derive(Debug, Serialize, Clone)]
 #[serde(untagged)]
 pub enum CsvRecord {
@@ -135,6 +499,20 @@ impl CsvRecord {
 		}
 	}
 
+	/// Total rewards and slashes over all records.
+	pub fn summary(&self) -> Summary {
+		match self {
+			CsvRecord::Grouped(v) => Summary {
+				rewards: v.iter().map(|r| r.amount).reduce(|a, b| a + b).unwrap_or_default(),
+				slashes: v.iter().map(|r| r.slash).reduce(|a, b| a + b).unwrap_or_default(),
+			},
+			CsvRecord::Separated(v) => Summary {
+				rewards: v.iter().map(|r| r.amount).reduce(|a, b| a + b).unwrap_or_default(),
+				slashes: v.iter().map(|r| r.slash).reduce(|a, b| a + b).unwrap_or_default(),
+			},
+		}
+	}
+
 	pub fn with_title(&self) -> TableStruct {
 		match self {
 			CsvRecord::Grouped(v) => v.with_title(),
@@ -142,70 +520,487 @@ impl CsvRecord {
 		}
 	}
 
-	pub fn serialize(self, wtr: &mut Output) -> Result<(), Error> {
+	pub fn is_empty(&self) -> bool {
+		match self {
+			CsvRecord::Grouped(v) => v.is_empty(),
+			CsvRecord::Separated(v) => v.is_empty(),
+		}
+	}
+
+	/// Label the records with the account and network they belong to.
+	pub fn to_portfolio(&self, label: &str, network: &Network) -> Vec<PortfolioRecord> {
+		let record = |date: &str, block_nums: &str, amount, slash, price: &OptionalPrice| PortfolioRecord {
+			label: label.to_string(),
+			network: network.id().to_string(),
+			token: network.symbol.clone(),
+			date: date.to_string(),
+			block_nums: block_nums.to_string(),
+			amount,
+			slash,
+			price: price.clone(),
+		};
+		match self {
+			CsvRecord::Grouped(v) => {
+				v.iter().map(|r| record(&r.date, &r.block_nums, r.amount, r.slash, &r.price)).collect()
+			}
+			CsvRecord::Separated(v) => {
+				v.iter().map(|r| record(&r.date, &r.block_number, r.amount, r.slash, &r.price)).collect()
+			}
+		}
+	}
+
+	/// Map the records into Koinly's universal template, with fiat values in `currency`.
+	///
+	/// Koinly deduplicates rows by their `TxHash`, but a day of grouped rewards has no single transaction, so grouped
+	/// rows are left without one. Export separated records to import rewards with their extrinsics.
+	pub fn to_koinly(&self, network: &Network, currency: &str) -> Vec<KoinlyRecord> {
+		match self {
+			CsvRecord::Grouped(v) => v
+				.iter()
+				.flat_map(|r| {
+					let time = r.day.and_time(NaiveTime::MIN);
+					let amounts = (r.amount, r.slash);
+					KoinlyRecord::new(time, network, currency, amounts, &r.price, &r.block_nums, "")
+				})
+				.collect(),
+			CsvRecord::Separated(v) => v
+				.iter()
+				.flat_map(|r| {
+					let time = r.day.and_time(r.time_of_day);
+					let amounts = (r.amount, r.slash);
+					KoinlyRecord::new(time, network, currency, amounts, &r.price, &r.block_number, &r.extrinsic_hash)
+				})
+				.collect(),
+		}
+	}
+
+	/// Write the records as JSON or NDJSON to `writer`.
+	pub fn write_json(&self, writer: impl Write, metadata: &Metadata, format: Format) -> Result<(), Error> {
 		match self {
 			CsvRecord::Grouped(v) => {
-				v.into_iter().try_for_each(|r| wtr.serialize(r).context("Faild to format CsvRecord"))?;
+				write_json(writer, metadata, v.iter().map(GroupedJsonRecord::from).collect(), format)
 			}
 			CsvRecord::Separated(v) => {
-				v.into_iter().try_for_each(|r| wtr.serialize(r).context("Faild to format CsvRecord"))?;
+				write_json(writer, metadata, v.iter().map(SeparatedJsonRecord::from).collect(), format)
+			}
+		}
+		.context("Failed to format records as JSON")
+	}
+
+	pub fn serialize(&self, wtr: &mut Output) -> Result<(), Error> {
+		match self {
+			CsvRecord::Grouped(v) => {
+				v.iter().try_for_each(|r| wtr.serialize(r).context("Faild to format CsvRecord"))?;
+			}
+			CsvRecord::Separated(v) => {
+				v.iter().try_for_each(|r| wtr.serialize(r).context("Faild to format CsvRecord"))?;
 			}
 		}
 		Ok(())
 	}
 }
 
-#[derive(Debug, Clone, Serialize)]
-pub struct OptionalPrice(Option<f64>);
+/// An exact amount of tokens, stored as planck alongside the decimals of the token.
+///
+/// Serialized as a decimal string, so no precision is lost in the output.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Amount {
+	planck: u128,
+	decimals: u8,
+	/// fractional digits to display. `None` displays as many as are needed to be exact.
+	precision: Option<u8>,
+	/// display a `,` instead of a `.` before the fractional digits
+	decimal_comma: bool,
+}
+
+impl Amount {
+	pub fn new(planck: u128, decimals: u8) -> Self {
+		Self { planck, decimals, precision: None, decimal_comma: false }
+	}
+
+	/// Display the amount with a decimal comma, as is common in e.g. German spreadsheets.
+	pub fn with_decimal_comma(self, decimal_comma: bool) -> Self {
+		Self { decimal_comma, ..self }
+	}
+
+	pub fn is_zero(&self) -> bool {
+		self.planck == 0
+	}
+
+	/// Lossy conversion, for calculating fiat values.
+	pub fn to_f64(self) -> f64 {
+		self.planck as f64 / 10f64.powi(self.decimals.into())
+	}
+
+	/// Round half-up to `precision` fractional digits. Rounding to more digits than the token has is a no-op.
+	pub fn round(self, precision: u8) -> Self {
+		if precision >= self.decimals {
+			return Self { precision: Some(precision), ..self };
+		}
+		let unit = 10u128.pow((self.decimals - precision).into());
+		let (quotient, remainder) = (self.planck / unit, self.planck % unit);
+		let quotient = if remainder >= unit - remainder { quotient.saturating_add(1) } else { quotient };
+		Self { planck: quotient.saturating_mul(unit), precision: Some(precision), ..self }
+	}
+}
+
+impl std::ops::Add for Amount {
+	type Output = Amount;
+	fn add(self, other: Amount) -> Amount {
+		debug_assert_eq!(self.decimals, other.decimals, "Added amounts of different tokens");
+		Amount { planck: self.planck + other.planck, ..self }
+	}
+}
+
+impl fmt::Display for Amount {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let unit = 10u128.pow(self.decimals.into());
+		let whole = self.planck / unit;
+		let fraction = format!("{:0width$}", self.planck % unit, width = usize::from(self.decimals));
+		let fraction = match self.precision {
+			Some(precision) => {
+				format!("{fraction:0<width$}", width = usize::from(precision))[..precision.into()].to_string()
+			}
+			None => fraction.trim_end_matches('0').to_string(),
+		};
+		let separator = if self.decimal_comma { ',' } else { '.' };
+		if fraction.is_empty() {
+			write!(f, "{whole}")
+		} else {
+			write!(f, "{whole}{separator}{fraction}")
+		}
+	}
+}
+
+impl Serialize for Amount {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_str(self)
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+	pub rewards: Amount,
+	pub slashes: Amount,
+}
+
+impl fmt::Display for Summary {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "Total rewards: {}, total slashes: {}", self.rewards, self.slashes)
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct OptionalPrice {
+	price: Option<f64>,
+	/// display a `,` instead of a `.` before the fractional digits
+	decimal_comma: bool,
+}
+
 impl OptionalPrice {
 	pub fn new(price: Option<f64>) -> Self {
-		Self(price)
+		Self { price, decimal_comma: false }
+	}
+
+	pub fn get(&self) -> Option<f64> {
+		self.price
+	}
+
+	/// Display the price with a decimal comma, as is common in e.g. German spreadsheets.
+	pub fn with_decimal_comma(self, decimal_comma: bool) -> Self {
+		Self { decimal_comma, ..self }
 	}
 }
+
 impl fmt::Display for OptionalPrice {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-		if let Some(p) = self.0 {
-			write!(f, "{p}")
+		match self.price {
+			Some(p) if self.decimal_comma => write!(f, "{}", p.to_string().replace('.', ",")),
+			Some(p) => write!(f, "{p}"),
+			None => write!(f, ""),
+		}
+	}
+}
+
+impl Serialize for OptionalPrice {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		if self.decimal_comma {
+			serializer.collect_str(self)
 		} else {
-			write!(f, "")
+			self.price.serialize(serializer)
 		}
 	}
 }
 
 impl From<Option<f64>> for OptionalPrice {
 	fn from(price: Option<f64>) -> OptionalPrice {
-		OptionalPrice(price)
+		OptionalPrice::new(price)
 	}
 }
 
 impl From<&Option<f64>> for OptionalPrice {
 	fn from(price: &Option<f64>) -> OptionalPrice {
-		OptionalPrice(*price)
+		OptionalPrice::new(*price)
+	}
+}
+
+/// Format of the rewards of an account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+	/// delimiter-separated values in the configured [`Dialect`], with amounts and dates formatted for humans
+	Csv,
+	/// a JSON document with the metadata of the account and a list of rewards
+	Json,
+	/// one JSON object per reward and line, each with the metadata of the account
+	Ndjson,
+	/// Koinly's universal CSV template, to import rewards into Koinly
+	Koinly,
+}
+
+impl FromStr for Format {
+	type Err = Error;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"csv" => Ok(Format::Csv),
+			"json" => Ok(Format::Json),
+			"ndjson" => Ok(Format::Ndjson),
+			"koinly" => Ok(Format::Koinly),
+			_ => bail!("Format must be one of: 'csv', 'json', 'ndjson', 'koinly'."),
+		}
+	}
+}
+
+impl Format {
+	pub fn extension(&self) -> &'static str {
+		match self {
+			Format::Csv | Format::Koinly => "csv",
+			Format::Json => "json",
+			Format::Ndjson => "ndjson",
+		}
+	}
+}
+
+/// The file at `path`, or STDOUT if `stdout` is set.
+pub fn writer(stdout: bool, path: &Path) -> Result<Box<dyn Write>, Error> {
+	if stdout {
+		Ok(Box::new(io::stdout()))
+	} else {
+		let file = File::create(path).map_err(|source| err::Error::Io { path: path.to_path_buf(), source })?;
+		Ok(Box::new(BufWriter::new(file)))
+	}
+}
+
+/// When to quote the fields of CSV output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuoteStyle {
+	/// quote every field
+	Always,
+	/// quote fields which contain the delimiter, a quote or a line break
+	Necessary,
+	/// quote every field which is not a number
+	NonNumeric,
+	/// never quote fields, even if the output can not be read back
+	Never,
+}
+
+impl FromStr for QuoteStyle {
+	type Err = Error;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"always" => Ok(QuoteStyle::Always),
+			"necessary" => Ok(QuoteStyle::Necessary),
+			"non-numeric" => Ok(QuoteStyle::NonNumeric),
+			"never" => Ok(QuoteStyle::Never),
+			_ => bail!("Quote style must be one of: 'always', 'necessary', 'non-numeric', 'never'."),
+		}
+	}
+}
+
+impl From<QuoteStyle> for csv::QuoteStyle {
+	fn from(style: QuoteStyle) -> csv::QuoteStyle {
+		match style {
+			QuoteStyle::Always => csv::QuoteStyle::Always,
+			QuoteStyle::Necessary => csv::QuoteStyle::Necessary,
+			QuoteStyle::NonNumeric => csv::QuoteStyle::NonNumeric,
+			QuoteStyle::Never => csv::QuoteStyle::Never,
+		}
+	}
+}
+
+/// How CSV output is written. The decimal separator is chosen when the records are built, see
+/// [`Amount::with_decimal_comma`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dialect {
+	pub delimiter: u8,
+	/// whether to start with a row of column names
+	pub header: bool,
+	pub quote_style: QuoteStyle,
+}
+
+impl Default for Dialect {
+	fn default() -> Self {
+		Self { delimiter: b';', header: true, quote_style: QuoteStyle::Necessary }
+	}
+}
+
+impl Dialect {
+	/// The dialect Koinly imports.
+	pub fn koinly() -> Self {
+		Self { delimiter: b',', ..Self::default() }
 	}
 }
 
-pub enum Output {
-	FileOut(csv::Writer<File>),
-	StdOut(csv::Writer<std::io::Stdout>),
+pub struct Output {
+	writer: csv::Writer<Box<dyn Write>>,
+	header: bool,
 }
 
 impl Output {
-	pub fn new(app: &App) -> Result<Self, Error> {
-		let mut builder = csv::WriterBuilder::new();
-		builder.delimiter(b';');
-		if app.stdout {
-			Ok(Output::StdOut(builder.from_writer(io::stdout())))
-		} else {
-			let file = File::create(&app.folder)?;
-			Ok(Output::FileOut(builder.from_writer(file)))
+	/// Output to the file at `path`, or to STDOUT if `stdout` is set.
+	pub fn new(stdout: bool, path: &Path, dialect: &Dialect) -> Result<Self, Error> {
+		let writer = csv::WriterBuilder::new()
+			.delimiter(dialect.delimiter)
+			.has_headers(dialect.header)
+			.quote_style(dialect.quote_style.into())
+			.from_writer(writer(stdout, path)?);
+		Ok(Output { writer, header: dialect.header })
+	}
+
+	/// Write the column names of records written with [`Output::write_record`], unless the header is disabled.
+	pub fn write_header<I, T>(&mut self, header: I) -> Result<(), Error>
+	where
+		I: IntoIterator<Item = T>,
+		T: AsRef<[u8]>,
+	{
+		if self.header {
+			self.write_record(header)?;
 		}
+		Ok(())
+	}
+
+	/// Write a record which does not derive `Serialize`, like a row with a dynamic number of columns.
+	pub fn write_record<I, T>(&mut self, record: I) -> Result<(), Error>
+	where
+		I: IntoIterator<Item = T>,
+		T: AsRef<[u8]>,
+	{
+		self.writer.write_record(record)?;
+		Ok(())
 	}
 
 	pub fn serialize<T: serde::Serialize>(&mut self, val: T) -> Result<(), Error> {
-		match self {
-			Output::FileOut(f) => f.serialize(val)?,
-			Output::StdOut(s) => s.serialize(val)?,
-		};
+		self.writer.serialize(val)?;
 		Ok(())
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn reward(event_id: &str, amount: &str) -> Reward {
+		Reward {
+			event_index: "100-1".into(),
+			block_num: 100,
+			extrinsic_idx: 1,
+			module_id: "staking".into(),
+			event_id: event_id.into(),
+			params: serde_json::Value::Null,
+			extrinsic_hash: "0x00".into(),
+			event_idx: 1,
+			amount: amount.into(),
+			block_timestamp: 0,
+		}
+	}
+
+	#[test]
+	fn amounts_are_parsed_from_planck() {
+		assert_eq!(reward("Rewarded", "15000000000").amounts().unwrap(), (15_000_000_000, 0));
+		assert_eq!(reward("Slashed", "1000000000").amounts().unwrap(), (0, 1_000_000_000));
+		// more than fits into a u64
+		assert_eq!(reward("Rewarded", "100000000000000000000000").amounts().unwrap().0, 10u128.pow(23));
+		assert!(reward("Rewarded", "1.5").amounts().is_err());
+		assert!(reward("Rewarded", "-1").amounts().is_err());
+	}
+
+	fn response<T: serde::de::DeserializeOwned>(json: &str) -> ApiResponse<T> {
+		serde_json::from_str(json).unwrap()
+	}
+
+	#[test]
+	fn responses_without_data_are_not_subscan_errors() {
+		let empty = r#"{"code":0,"message":"Success","generated_at":1672531200,"data":null}"#;
+		let page = response::<List<Reward>>(empty).consume_or_default().unwrap();
+		assert_eq!((page.count, page.list.is_none()), (0, true));
+		let error = response::<Tokens>(empty).consume().unwrap_err();
+		assert!(matches!(error, err::Error::Decode { .. }), "{:?}", error);
+		assert_eq!(error.exit_code(), err::EXIT_DECODE);
+
+		let failed = r#"{"code":10004,"message":"Record Not Found","data":null}"#;
+		let error = response::<List<Reward>>(failed).consume_or_default().unwrap_err();
+		assert!(matches!(error, err::Error::Api { code: 10004, .. }), "{:?}", error);
+	}
+
+	#[test]
+	fn amounts_are_displayed_exactly() {
+		assert_eq!(Amount::new(15_000_000_000, 10).to_string(), "1.5");
+		assert_eq!(Amount::new(10_000_000_000, 10).to_string(), "1");
+		assert_eq!(Amount::new(0, 10).to_string(), "0");
+		// leading zeros of the fraction are kept
+		assert_eq!(Amount::new(5, 10).to_string(), "0.0000000005");
+		assert_eq!(Amount::new(1_000_000_005, 10).to_string(), "0.1000000005");
+		// more digits than the token has decimals
+		assert_eq!(Amount::new(123_456_789_012_345, 10).to_string(), "12345.6789012345");
+		assert_eq!(Amount::new(u128::MAX, 18).to_string(), "340282366920938463463.374607431768211455");
+		assert_eq!(Amount::new(42, 0).to_string(), "42");
+	}
+
+	#[test]
+	fn amounts_are_rounded_half_up() {
+		assert_eq!(Amount::new(12_344, 4).round(3).to_string(), "1.234");
+		assert_eq!(Amount::new(12_345, 4).round(3).to_string(), "1.235");
+		// the carry reaches the whole part
+		assert_eq!(Amount::new(99_995, 5).round(4).to_string(), "1.0000");
+		assert_eq!(Amount::new(99_994, 5).round(4).to_string(), "0.9999");
+		assert_eq!(Amount::new(15, 1).round(0).to_string(), "2");
+		assert_eq!(Amount::new(14, 1).round(0).to_string(), "1");
+		assert_eq!(Amount::new(0, 10).round(0).to_string(), "0");
+	}
+
+	#[test]
+	fn rounded_amounts_are_padded_to_the_precision() {
+		assert_eq!(Amount::new(15, 1).round(3).to_string(), "1.500");
+		assert_eq!(Amount::new(10_000_000_000, 10).round(2).to_string(), "1.00");
+		assert_eq!(Amount::new(5, 10).round(4).to_string(), "0.0000");
+	}
+
+	#[test]
+	fn charts_are_split_into_the_first_price_of_every_day() {
+		let chart: MarketChart = serde_json::from_str(
+			r#"{"prices": [[1672538400000, 4.31], [1672531200000, 4.25], [1672534800000, 4.27], [1672617600000, 4.5]]}"#,
+		)
+		.unwrap();
+		let day = |d| NaiveDate::from_ymd_opt(2023, 1, d).unwrap();
+		assert_eq!(chart.opening_prices(), BTreeMap::from([(day(1), 4.25), (day(2), 4.5)]));
+	}
+
+	#[test]
+	fn grouped_koinly_rows_have_no_tx_hash() {
+		let registry = crate::network::Registry::builtin();
+		let day: NaiveDate = "2023-01-01".parse().unwrap();
+		let records = CsvRecord::Grouped(vec![GroupedCsvRecord {
+			day,
+			blocks: vec![100, 150],
+			date: day.to_string(),
+			block_nums: "100+150".into(),
+			amount: Amount::new(15_000_000_000, 10),
+			slash: Amount::new(1_000_000_000, 10),
+			price: OptionalPrice::new(Some(4.25)),
+		}]);
+		let rows = records.to_koinly(registry.get("polkadot").unwrap(), "usd");
+		assert_eq!(
+			rows.iter().map(|r| (r.label.as_str(), r.tx_hash.as_str())).collect::<Vec<_>>(),
+			[(KOINLY_REWARD, ""), (KOINLY_SLASH, "")]
+		);
+	}
+}
@@ -0,0 +1,197 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of polkadot-rewards.
+
+// polkadot-rewards is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// polkadot-rewards is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with polkadot-rewards.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Portfolio report, merging the rewards of many networks into a single time series.
+
+use crate::{
+	network::Network,
+	primitives::{Amount, CsvRecord, OptionalPrice, Output},
+};
+use anyhow::Error;
+use chrono::NaiveDate;
+use cli_table::{Cell, Table, TableStruct};
+use std::collections::BTreeMap;
+
+/// Rewards of one network on one day, summed over all accounts.
+#[derive(Debug, Clone, Copy)]
+struct Holding {
+	amount: Amount,
+	slash: Amount,
+	/// fiat value of `amount`. `None` if prices were not gathered.
+	///
+	/// Slashes are not subtracted: like in the CSV output of each account, they are kept apart from rewards.
+	value: Option<f64>,
+}
+
+impl Holding {
+	fn new(amount: Amount, slash: Amount, price: &OptionalPrice) -> Self {
+		Self { amount, slash, value: price.get().map(|p| amount.to_f64() * p) }
+	}
+
+	fn merge(self, other: Holding) -> Holding {
+		Holding {
+			amount: self.amount + other.amount,
+			slash: self.slash + other.slash,
+			value: self.value.zip(other.value).map(|(a, b)| a + b),
+		}
+	}
+}
+
+/// A time series of the rewards of many networks, with their fiat value and the total fiat value of each day.
+///
+/// Fiat values are those of the rewards; slashes are reported in their own columns, but not subtracted.
+#[derive(Debug, Default)]
+pub struct PortfolioReport {
+	/// name and token symbol of the networks in the report, in the order of their columns.
+	networks: Vec<(String, String)>,
+	days: BTreeMap<NaiveDate, BTreeMap<String, Holding>>,
+	/// display fiat values with a decimal comma
+	decimal_comma: bool,
+	/// whether any reward came with a price. Without prices, values are left empty rather than shown as zero.
+	priced: bool,
+}
+
+impl PortfolioReport {
+	/// Display fiat values with a decimal comma. Amounts keep the separator of the records they were added from.
+	pub fn with_decimal_comma(self, decimal_comma: bool) -> Self {
+		Self { decimal_comma, ..self }
+	}
+
+	/// Add the rewards of an account on `network`.
+	pub fn add(&mut self, network: &Network, rewards: &CsvRecord) {
+		if !self.networks.iter().any(|(name, _)| name == network.id()) {
+			self.networks.push((network.id().to_string(), network.symbol.clone()));
+		}
+		let mut add = |day: NaiveDate, amount: Amount, slash: Amount, price: &OptionalPrice| {
+			self.priced |= price.get().is_some();
+			let holding = Holding::new(amount, slash, price);
+			self.days
+				.entry(day)
+				.or_default()
+				.entry(network.id().to_string())
+				.and_modify(|h| *h = h.merge(holding))
+				.or_insert(holding);
+		};
+		match rewards {
+			CsvRecord::Grouped(v) => v.iter().for_each(|r| add(r.day, r.amount, r.slash, &r.price)),
+			CsvRecord::Separated(v) => v.iter().for_each(|r| add(r.day, r.amount, r.slash, &r.price)),
+		}
+	}
+
+	fn header(&self) -> Vec<String> {
+		let mut header = vec!["date".to_string()];
+		for (name, symbol) in &self.networks {
+			header.push(format!("{name} amount ({symbol})"));
+			header.push(format!("{name} slash ({symbol})"));
+			header.push(format!("{name} value"));
+		}
+		header.push("total value".to_string());
+		header
+	}
+
+	/// Rows of the report, newest day first.
+	fn rows(&self, date_format: &str) -> Vec<Vec<String>> {
+		self.days
+			.iter()
+			.rev()
+			.map(|(day, holdings)| {
+				let value = |value: Option<f64>| OptionalPrice::from(value).with_decimal_comma(self.decimal_comma);
+				let mut row = vec![day.format(date_format).to_string()];
+				let mut total = Some(0f64);
+				for (name, _) in &self.networks {
+					match holdings.get(name) {
+						Some(holding) => {
+							row.push(holding.amount.to_string());
+							row.push(holding.slash.to_string());
+							row.push(value(holding.value).to_string());
+							total = total.zip(holding.value).map(|(a, b)| a + b);
+						}
+						// no rewards of this network on that day
+						None => row.extend([
+							"0".to_string(),
+							"0".to_string(),
+							value(self.priced.then_some(0.0)).to_string(),
+						]),
+					}
+				}
+				row.push(value(total).to_string());
+				row
+			})
+			.collect()
+	}
+
+	pub fn serialize(&self, wtr: &mut Output, date_format: &str) -> Result<(), Error> {
+		wtr.write_header(self.header())?;
+		self.rows(date_format).into_iter().try_for_each(|row| wtr.write_record(row))
+	}
+
+	pub fn with_title(&self, date_format: &str) -> TableStruct {
+		self.rows(date_format)
+			.into_iter()
+			.map(|row| row.into_iter().map(|c| c.cell()))
+			.table()
+			.title(self.header().into_iter().map(|h| h.cell()))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{network::Registry, primitives::GroupedCsvRecord};
+
+	fn rewards(day: &str, planck: u128, price: Option<f64>) -> CsvRecord {
+		let day: NaiveDate = day.parse().unwrap();
+		CsvRecord::Grouped(vec![GroupedCsvRecord {
+			day,
+			blocks: vec![1],
+			date: day.to_string(),
+			block_nums: "1".into(),
+			amount: Amount::new(planck, 10),
+			slash: Amount::new(0, 10),
+			price: OptionalPrice::new(price),
+		}])
+	}
+
+	fn report(price: Option<f64>) -> PortfolioReport {
+		let registry = Registry::builtin();
+		let mut report = PortfolioReport::default();
+		report.add(registry.get("polkadot").unwrap(), &rewards("2023-01-01", 10_000_000_000, price));
+		report.add(registry.get("kusama").unwrap(), &rewards("2023-01-02", 20_000_000_000, price));
+		report
+	}
+
+	#[test]
+	fn networks_without_rewards_are_worth_zero() {
+		assert_eq!(
+			report(Some(2.0)).rows("%Y-%m-%d"),
+			vec![
+				vec!["2023-01-02", "0", "0", "0", "2", "0", "4", "4"],
+				vec!["2023-01-01", "1", "0", "2", "0", "0", "0", "2"]
+			]
+		);
+	}
+
+	#[test]
+	fn values_are_empty_without_prices() {
+		assert_eq!(
+			report(None).rows("%Y-%m-%d"),
+			vec![
+				vec!["2023-01-02", "0", "0", "", "2", "0", "", ""],
+				vec!["2023-01-01", "1", "0", "", "0", "0", "", ""]
+			]
+		);
+	}
+}
@@ -0,0 +1,452 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of polkadot-rewards.
+
+// polkadot-rewards is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// polkadot-rewards is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with polkadot-rewards.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Sources of reward data. Subscan is the default, but anything that can hand out pages of rewards will do.
+
+use crate::{
+	http::Client,
+	network::Network,
+	pool,
+	primitives::{ApiResponse, List, Reward, TokenDetail, Tokens},
+};
+use anyhow::{bail, Context, Error};
+use chrono::naive::NaiveDateTime;
+use indicatif::ProgressBar;
+use std::{
+	convert::TryInto,
+	fs::File,
+	io::BufReader,
+	path::{Path, PathBuf},
+	str::FromStr,
+};
+
+const REWARD_SLASH: &str = "scan/account/reward_slash";
+const TOKEN: &str = "scan/token";
+
+/// How many rewards to request per page.
+pub const PAGE_SIZE: usize = 100;
+
+/// Requests per second sent to Subscan without an API key.
+pub const SUBSCAN_RPS: f64 = 3.0;
+/// Requests per second sent to Subscan with an API key, as allowed by the free plan. Paid plans allow more.
+pub const SUBSCAN_RPS_WITH_KEY: f64 = 5.0;
+
+pub fn get_endpoint(network: &Network, end: &str) -> String {
+	format!("{}{end}", network.subscan_url)
+}
+
+/// Where reward data is read from.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Source {
+	/// The Subscan HTTP API
+	Subscan,
+	/// A JSON file containing a list of rewards, as returned by Subscan.
+	File(PathBuf),
+}
+
+impl FromStr for Source {
+	type Err = Error;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.split_once(':') {
+			None if s.eq_ignore_ascii_case("subscan") => Ok(Source::Subscan),
+			Some((kind, path)) if kind.eq_ignore_ascii_case("file") && !path.is_empty() => {
+				Ok(Source::File(PathBuf::from(path)))
+			}
+			_ => bail!("Source must be one of: 'subscan', 'file:<path>'."),
+		}
+	}
+}
+
+/// Something that can provide the rewards of a single account, newest first.
+pub trait RewardSource: Send + Sync {
+	/// Get rewards from a specific page.
+	///
+	/// `page`: Which page to query, starting at `0`
+	/// `count`: How many to return in one request.
+	fn rewards(&self, page: usize, count: usize) -> Result<List<Reward>, Error>;
+
+	/// How many pages to request at once.
+	fn jobs(&self) -> usize {
+		1
+	}
+
+	/// Total number of rewards (and slashes) known for the account.
+	fn count(&self) -> Result<usize, Error> {
+		Ok(self.rewards(0, 1).context("Failed to fetch initial reward page")?.count)
+	}
+
+	/// Fetch all rewards starting from some point in time and ending at another.
+	///
+	/// Rewards are returned newest first, so pages newer than `to` are skipped
+	/// and fetching stops at the first page reaching past `from`.
+	fn fetch_range(
+		&self,
+		from: Option<NaiveDateTime>,
+		to: Option<NaiveDateTime>,
+		progress: Option<&ProgressBar>,
+	) -> Result<Vec<Reward>, Error> {
+		let first_page = match to {
+			Some(to) => self.first_page_until(to)?,
+			None => 0,
+		};
+
+		let mut rewards = Vec::new();
+		for_each_page(self, first_page, progress, pages_from(first_page), |list| {
+			let oldest = list.last().map(timestamp).transpose()?;
+			for reward in list {
+				let timestamp = timestamp(&reward)?;
+				let from = if let Some(from) = from { timestamp >= from } else { true };
+				let to = if let Some(to) = to { timestamp <= to } else { true };
+				if from && to {
+					rewards.push(reward);
+				}
+			}
+			// stop at the first page reaching past `from`
+			Ok(from.zip(oldest).is_none_or(|(from, oldest)| oldest >= from))
+		})?;
+		Ok(rewards)
+	}
+
+	/// Binary search for the first page with rewards at or before `to`.
+	fn first_page_until(&self, to: NaiveDateTime) -> Result<usize, Error> {
+		let count = self.count()?;
+		let (mut low, mut high) = (0, count.div_ceil(PAGE_SIZE));
+		while low < high {
+			let mid = (low + high) / 2;
+			// only fetch the oldest reward of the page
+			let oldest = ((mid + 1) * PAGE_SIZE).min(count) - 1;
+			let reward = self.rewards(oldest, 1)?.list.and_then(|l| l.into_iter().next());
+			match reward {
+				Some(reward) if timestamp(&reward)? > to => low = mid + 1,
+				_ => high = mid,
+			}
+		}
+		Ok(low)
+	}
+}
+
+/// Hand the pages of `source` from `start` on to `visit`, in order, until the last page, an empty page, or until
+/// `visit` returns `false`.
+///
+/// Up to [`RewardSource::jobs`] pages are fetched at once, but never more than the source had when it was last asked.
+/// Pages fetched at once may reach past the page `visit` stopped at.
+///
+/// `progress` advances with every page. Its length grows by `expected(count)` pages once the first page tells the
+/// number of rewards of the account.
+pub fn for_each_page<S: RewardSource + ?Sized>(
+	source: &S,
+	start: usize,
+	progress: Option<&ProgressBar>,
+	expected: impl FnOnce(usize) -> usize,
+	mut visit: impl FnMut(Vec<Reward>) -> Result<bool, Error>,
+) -> Result<(), Error> {
+	let mut expected = Some(expected);
+	let mut pages = None;
+	let mut next = start;
+	loop {
+		// the first page tells how many pages there are
+		let jobs = pages.map_or(1, |pages: usize| source.jobs().min(pages.saturating_sub(next)).max(1));
+		let wave = (next..next + jobs).collect::<Vec<_>>();
+		let lists = pool::map(&wave, jobs, |i| {
+			progress.map(|p| p.inc(1));
+			source.rewards(*i, PAGE_SIZE).with_context(|| format!("Failed to fetch page {i}"))
+		})?;
+		next += jobs;
+		for page in lists {
+			if let Some(expected) = expected.take() {
+				progress.map(|p| p.inc_length(expected(page.count).try_into().unwrap_or_default()));
+			}
+			pages = Some(page.count.div_ceil(PAGE_SIZE));
+			match page.list {
+				Some(list) if !list.is_empty() => {
+					if !visit(list)? {
+						return Ok(());
+					}
+				}
+				_ => return Ok(()),
+			}
+		}
+		// don't ask for the empty page after the last one
+		if pages.is_some_and(|pages| next >= pages) {
+			return Ok(());
+		}
+	}
+}
+
+/// The pages from `start` to the last one, given the number of rewards of the account.
+pub fn pages_from(start: usize) -> impl FnOnce(usize) -> usize {
+	move |count| count.div_ceil(PAGE_SIZE).saturating_sub(start)
+}
+
+pub fn timestamp(reward: &Reward) -> Result<NaiveDateTime, Error> {
+	NaiveDateTime::from_timestamp_opt(reward.block_timestamp.try_into()?, 0)
+		.context("Block timestamp is out of range. This is a bug")
+}
+
+/// Rewards from the Subscan API
+pub struct Subscan<'a> {
+	client: Client,
+	network: Network,
+	address: &'a str,
+	api_key: &'a str,
+	jobs: usize,
+}
+
+impl<'a> Subscan<'a> {
+	pub fn new(client: Client, network: Network, address: &'a str, api_key: &'a str) -> Self {
+		Self { client, network, address, api_key, jobs: 1 }
+	}
+
+	/// Request up to `jobs` pages at once.
+	pub fn with_jobs(mut self, jobs: usize) -> Self {
+		self.jobs = jobs;
+		self
+	}
+
+	/// Get the symbol and decimals of the networks native token from the chain metadata.
+	pub fn token(&self) -> Result<TokenDetail, Error> {
+		let tokens: ApiResponse<Tokens> = self
+			.client
+			.post_json(&get_endpoint(&self.network, TOKEN), self.api_key, ureq::json!({}))
+			.with_context(|| format!("Failed to fetch token metadata of {}", self.network.id()))?;
+		let Tokens { token, mut detail } = tokens.consume()?;
+		// the first token is the native token of the network
+		token
+			.first()
+			.and_then(|symbol| detail.remove(symbol))
+			.with_context(|| format!("Subscan returned no native token for {}", self.network.id()))
+	}
+}
+
+impl<'a> RewardSource for Subscan<'a> {
+	fn jobs(&self) -> usize {
+		self.jobs
+	}
+
+	fn rewards(&self, page: usize, count: usize) -> Result<List<Reward>, Error> {
+		let body = ureq::json!({
+			"address": self.address,
+			"page": page,
+			"row": count
+		});
+		let rewards: ApiResponse<List<Reward>> = self
+			.client
+			.post_json(&get_endpoint(&self.network, REWARD_SLASH), self.api_key, body)
+			.with_context(|| format!("Failed to fetch reward for address={} page={page} row={count}", self.address))?;
+		// Subscan may answer with no data at all instead of an empty list
+		Ok(rewards.consume_or_default()?)
+	}
+}
+
+/// Rewards read from a local JSON file. Useful for testing, or for data exported from another indexer.
+///
+/// The file may contain either a plain list of rewards, or the `data` object of a subscan response.
+pub struct JsonFile {
+	rewards: Vec<Reward>,
+}
+
+impl JsonFile {
+	pub fn open(path: &Path) -> Result<Self, Error> {
+		let file = File::open(path).with_context(|| format!("Failed to open reward source {}", path.display()))?;
+		let value: serde_json::Value = serde_json::from_reader(BufReader::new(file))
+			.with_context(|| format!("Failed to read reward source {}", path.display()))?;
+		let mut rewards: Vec<Reward> = if value.is_array() {
+			serde_json::from_value(value)?
+		} else {
+			serde_json::from_value::<List<Reward>>(value)?.list.unwrap_or_default()
+		};
+		// sources hand out rewards newest first, like subscan does.
+		rewards.sort_by_key(|r| std::cmp::Reverse(r.block_timestamp));
+		Ok(Self { rewards })
+	}
+}
+
+impl RewardSource for JsonFile {
+	fn rewards(&self, page: usize, count: usize) -> Result<List<Reward>, Error> {
+		let list = self.rewards.iter().skip(page.saturating_mul(count)).take(count).cloned().collect::<Vec<_>>();
+		Ok(List { count: self.rewards.len(), list: Some(list) })
+	}
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+	use std::sync::Mutex;
+
+	/// Timestamp of the newest reward of a [`Fake`] source. Every older reward is an hour older.
+	const NEWEST: usize = 1_672_531_200;
+
+	/// A source of `len` hourly rewards, which remembers every request.
+	pub(crate) struct Fake {
+		rewards: Mutex<Vec<Reward>>,
+		requests: Mutex<Vec<(usize, usize)>>,
+		jobs: usize,
+	}
+
+	impl Fake {
+		pub(crate) fn new(len: usize) -> Self {
+			Self { rewards: Mutex::new((0..len).map(reward).collect()), requests: Mutex::default(), jobs: 1 }
+		}
+
+		pub(crate) fn with_jobs(mut self, jobs: usize) -> Self {
+			self.jobs = jobs;
+			self
+		}
+
+		/// Pay out a reward newer than all others.
+		pub(crate) fn push_newest(&self) {
+			let mut rewards = self.rewards.lock().unwrap();
+			let newest = rewards.first().map_or(NEWEST, |r| r.block_timestamp) + 3600;
+			let reward = Reward { event_index: format!("{newest}-1"), block_timestamp: newest, ..reward(0) };
+			rewards.insert(0, reward);
+		}
+
+		/// `(page, count)` of all requests since the last call, in order.
+		pub(crate) fn requests(&self) -> Vec<(usize, usize)> {
+			std::mem::take(&mut *self.requests.lock().unwrap())
+		}
+
+		/// Pages of rewards requested since the last call, in order.
+		pub(crate) fn pages(&self) -> Vec<usize> {
+			self.requests().into_iter().filter(|(_, count)| *count == PAGE_SIZE).map(|(page, _)| page).collect()
+		}
+	}
+
+	/// Lets a [`Fake`] be wrapped by other sources while its requests are checked.
+	impl RewardSource for &Fake {
+		fn rewards(&self, page: usize, count: usize) -> Result<List<Reward>, Error> {
+			(*self).rewards(page, count)
+		}
+
+		fn jobs(&self) -> usize {
+			self.jobs
+		}
+	}
+
+	impl RewardSource for Fake {
+		fn rewards(&self, page: usize, count: usize) -> Result<List<Reward>, Error> {
+			self.requests.lock().unwrap().push((page, count));
+			let rewards = self.rewards.lock().unwrap();
+			let list = rewards.iter().skip(page * count).take(count).cloned().collect();
+			Ok(List { count: rewards.len(), list: Some(list) })
+		}
+
+		fn jobs(&self) -> usize {
+			self.jobs
+		}
+	}
+
+	/// The `i`-th newest reward.
+	pub(crate) fn reward(i: usize) -> Reward {
+		Reward {
+			event_index: format!("{}-1", 10_000 - i),
+			block_num: (10_000 - i) as u64,
+			extrinsic_idx: 1,
+			module_id: "staking".into(),
+			event_id: "Rewarded".into(),
+			params: serde_json::Value::Null,
+			extrinsic_hash: format!("0x{i:064x}"),
+			event_idx: 1,
+			amount: "10000000000".into(),
+			block_timestamp: NEWEST - i * 3600,
+		}
+	}
+
+	/// Time of the `i`-th newest reward.
+	pub(crate) fn time(i: usize) -> NaiveDateTime {
+		timestamp(&reward(i)).unwrap()
+	}
+
+	/// Indices of the rewards, `0` being the newest.
+	pub(crate) fn indices(rewards: &[Reward]) -> Vec<usize> {
+		rewards.iter().map(|r| 10_000 - r.block_num as usize).collect()
+	}
+
+	#[test]
+	fn all_pages_are_fetched_without_a_range() {
+		let source = Fake::new(350);
+		let rewards = source.fetch_range(None, None, None).unwrap();
+		assert_eq!(indices(&rewards), (0..350).collect::<Vec<_>>());
+		assert_eq!(source.pages(), vec![0, 1, 2, 3]);
+	}
+
+	#[test]
+	fn fetching_stops_at_the_first_page_past_from() {
+		let source = Fake::new(350);
+		let rewards = source.fetch_range(Some(time(150)), None, None).unwrap();
+		assert_eq!(indices(&rewards), (0..=150).collect::<Vec<_>>());
+		assert_eq!(source.pages(), vec![0, 1]);
+		// the oldest reward of page 0 is not older than `from`, so page 1 may still hold rewards of the range
+		let rewards = source.fetch_range(Some(time(99)), None, None).unwrap();
+		assert_eq!(indices(&rewards), (0..=99).collect::<Vec<_>>());
+		assert_eq!(source.pages(), vec![0, 1]);
+		let rewards = source.fetch_range(Some(time(98)), None, None).unwrap();
+		assert_eq!(indices(&rewards), (0..=98).collect::<Vec<_>>());
+		assert_eq!(source.pages(), vec![0]);
+	}
+
+	#[test]
+	fn pages_newer_than_to_are_skipped() {
+		let source = Fake::new(350);
+		let rewards = source.fetch_range(None, Some(time(250)), None).unwrap();
+		assert_eq!(indices(&rewards), (250..350).collect::<Vec<_>>());
+		assert_eq!(source.pages(), vec![2, 3]);
+		// `to` at the last reward of a page, and at the first
+		source.fetch_range(None, Some(time(199)), None).unwrap();
+		assert_eq!(source.pages(), vec![1, 2, 3]);
+		source.fetch_range(None, Some(time(200)), None).unwrap();
+		assert_eq!(source.pages(), vec![2, 3]);
+	}
+
+	#[test]
+	fn only_pages_of_the_range_are_fetched() {
+		let source = Fake::new(350);
+		let rewards = source.fetch_range(Some(time(260)), Some(time(130)), None).unwrap();
+		assert_eq!(indices(&rewards), (130..=260).collect::<Vec<_>>());
+		assert_eq!(source.pages(), vec![1, 2]);
+	}
+
+	#[test]
+	fn ranges_outside_the_history_are_empty() {
+		let source = Fake::new(350);
+		assert!(source.fetch_range(None, Some(time(400)), None).unwrap().is_empty());
+		assert!(source.fetch_range(Some(time(0) + chrono::Duration::hours(1)), None, None).unwrap().is_empty());
+		assert_eq!(source.pages(), vec![4, 0]);
+		let source = Fake::new(0);
+		assert!(source.fetch_range(Some(time(10)), Some(time(5)), None).unwrap().is_empty());
+	}
+
+	#[test]
+	fn the_binary_search_only_fetches_single_rewards() {
+		let source = Fake::new(10_000);
+		assert_eq!(source.first_page_until(time(5_050)).unwrap(), 50);
+		let requests = source.requests();
+		assert!(requests.iter().all(|(_, count)| *count == 1));
+		// the count, and a probe per halving of the 100 pages
+		assert!(requests.len() <= 1 + 8, "{:?}", requests);
+	}
+
+	#[test]
+	fn pages_fetched_at_once_are_kept_in_order() {
+		let source = Fake::new(950).with_jobs(4);
+		let rewards = source.fetch_range(Some(time(720)), Some(time(130)), None).unwrap();
+		assert_eq!(indices(&rewards), (130..=720).collect::<Vec<_>>());
+		let mut pages = source.pages();
+		pages.sort();
+		// page 7 is the first past `from`, but it was fetched together with 6, 8 and 9
+		assert_eq!(pages, (1..=9).collect::<Vec<_>>());
+	}
+}
@@ -0,0 +1,323 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of polkadot-rewards.
+
+// polkadot-rewards is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// polkadot-rewards is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with polkadot-rewards.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Runs the CLI end to end against the responses in `tests/fixtures`, without network access.
+//!
+//! The fixtures are written in the format of `--record`. They hold the rewards of one polkadot account, an account
+//! without rewards and an account Subscan refuses to answer for, and CoinGecko prices of the days of the rewards: on
+//! their own, and as a USD chart of both days that only has prices of the first one. The kusama fixtures hold the
+//! rewards of the same key on kusama.
+
+use std::{
+	fs,
+	io::{BufRead, BufReader},
+	path::{Path, PathBuf},
+	process::{Child, Command, Output, Stdio},
+};
+use tempfile::TempDir;
+
+/// rewards 1.5 DOT and slashes 0.1 DOT on 2023-01-01, rewards 2.5 DOT on 2023-01-02
+const REWARDED: &str = "11JNArUumxYJcSQpbuxuroRZtcSMVLcy5WbYGt14SRkztH";
+/// the public key of `REWARDED`
+const REWARDED_KEY: &str = "0x000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+const NO_REWARDS: &str = "12KM5KYi2fBdRoijHVrpPx71buoU5bG8Yq7rVpEG7nrUG6f";
+/// Subscan answers with an invalid API key error
+const INVALID_KEY: &str = "13dh9e6R4KNFrcSTZzidnuD2CpbwABXG6fEhzdTXEahwG9h";
+/// no responses are recorded for this account
+const NOT_RECORDED: &str = "14x3Dxe85yYtHRABrVaTBrK2ojQQEmnPeVMZVSgnMNZQhPf";
+
+fn fixtures() -> PathBuf {
+	Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/polkadot")
+}
+
+/// Run the CLI with `args`, replaying the polkadot fixtures with an empty cache in `dir`.
+fn run(dir: &TempDir, args: &[&str]) -> Output {
+	run_live(dir, &[&["--replay", fixtures().to_str().unwrap()][..], args].concat())
+}
+
+/// Run the CLI with `args` and an empty cache in `dir`.
+fn run_live(dir: &TempDir, args: &[&str]) -> Output {
+	Command::new(env!("CARGO_BIN_EXE_polkadot-rewards"))
+		.arg("--cache-dir")
+		.arg(dir.path().join("cache"))
+		.args(args)
+		.output()
+		.expect("Failed to run polkadot-rewards")
+}
+
+fn stdout(output: &Output) -> String {
+	assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+	String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn rewards_are_grouped_by_day_with_prices() {
+	let dir = TempDir::new().unwrap();
+	let output = run(&dir, &["-a", REWARDED, "-s"]);
+	assert_eq!(
+		stdout(&output),
+		"date;block_nums;amount;slash;price\n2023-01-02;200;2.5;0;4.5\n2023-01-01;100+150;1.5;0.1;4.25\n"
+	);
+}
+
+#[test]
+fn rewards_are_separated_with_no_group() {
+	let dir = TempDir::new().unwrap();
+	let output = run(&dir, &["-a", REWARDED, "-s", "--no-group", "-c", "eur"]);
+	assert_eq!(
+		stdout(&output),
+		"date;time;block_number;amount;slash;price\n\
+		 2023-01-02;12:00:00;200;2.5;0;4.2\n\
+		 2023-01-01;12:01:40;150;0;0.1;3.98\n\
+		 2023-01-01;12:00:00;100;1.5;0;3.98\n"
+	);
+}
+
+#[test]
+fn price_ranges_are_fetched_at_once_and_gaps_day_by_day() {
+	let dir = TempDir::new().unwrap();
+	// the USD chart only has prices of 2023-01-01, and that day can't be looked up on its own
+	let replay = dir.path().join("fixtures");
+	fs::create_dir(&replay).unwrap();
+	for entry in fs::read_dir(fixtures()).unwrap() {
+		let path = entry.unwrap().path();
+		if !path.ends_with("api_coingecko_com_api_v3_coins_polkadot_history-7661b9eaacfc60d1.json") {
+			fs::copy(&path, replay.join(path.file_name().unwrap())).unwrap();
+		}
+	}
+	let output = run_live(&dir, &["--replay", replay.to_str().unwrap(), "-a", REWARDED, "-s"]);
+	assert_eq!(
+		stdout(&output),
+		"date;block_nums;amount;slash;price\n2023-01-02;200;2.5;0;4.5\n2023-01-01;100+150;1.5;0.1;4.25\n"
+	);
+}
+
+#[test]
+fn csv_dialect_is_configurable() {
+	let dir = TempDir::new().unwrap();
+	let german = run(&dir, &["-a", REWARDED, "-s", "--decimal-comma"]);
+	assert_eq!(
+		stdout(&german),
+		"date;block_nums;amount;slash;price\n2023-01-02;200;2,5;0;4,5\n2023-01-01;100+150;1,5;0,1;4,25\n"
+	);
+	let quoted = run(&dir, &["-a", REWARDED, "-s", "--delimiter", ",", "--decimal-comma", "--no-header"]);
+	assert_eq!(stdout(&quoted), "2023-01-02,200,\"2,5\",0,\"4,5\"\n2023-01-01,100+150,\"1,5\",\"0,1\",\"4,25\"\n");
+	let tabbed = run(&dir, &["-a", REWARDED, "-s", "--delimiter", "tab", "--quote-style", "always", "--no-header"]);
+	assert_eq!(stdout(&tabbed).lines().next(), Some("\"2023-01-02\"\t\"200\"\t\"2.5\"\t\"0\"\t\"4.5\""));
+}
+
+#[test]
+fn json_has_typed_fields_and_metadata() {
+	let dir = TempDir::new().unwrap();
+	let output = run(&dir, &["-a", REWARDED, "-s", "--format", "json"]);
+	let json: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+	assert_eq!(json["network"], "polkadot");
+	assert_eq!(json["address"], REWARDED);
+	assert_eq!(json["token"], "DOT");
+	assert_eq!(json["currency"], "usd");
+	assert!(json["generated_at"].is_string());
+	assert_eq!(
+		json["rewards"][1],
+		serde_json::json!({ "date": "2023-01-01", "blocks": [100, 150], "amount": 1.5, "slash": 0.1, "price": 4.25 })
+	);
+}
+
+#[test]
+fn ndjson_has_one_reward_per_line() {
+	let dir = TempDir::new().unwrap();
+	let output = run(&dir, &["-a", REWARDED, "-s", "--format", "ndjson", "--no-group"]);
+	let lines = stdout(&output)
+		.lines()
+		.map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+		.collect::<Vec<_>>();
+	assert_eq!(lines.len(), 3);
+	assert!(lines.iter().all(|line| line["network"] == "polkadot" && line["address"] == REWARDED));
+	assert_eq!(lines[1]["date"], "2023-01-01");
+	assert_eq!(lines[1]["time"], "12:01:40");
+	assert_eq!(lines[1]["block"], 150);
+	assert_eq!(lines[1]["slash"], 0.1);
+}
+
+#[test]
+fn koinly_books_rewards_and_slashes_with_their_extrinsics() {
+	let dir = TempDir::new().unwrap();
+	let output = run(&dir, &["-a", REWARDED, "-s", "--format", "koinly", "--no-group"]);
+	let csv = stdout(&output);
+	let mut lines = csv.lines();
+	assert_eq!(
+		lines.next(),
+		Some(
+			"Date,Sent Amount,Sent Currency,Received Amount,Received Currency,Fee Amount,Fee Currency,\
+			 Net Worth Amount,Net Worth Currency,Label,Description,TxHash"
+		)
+	);
+	assert_eq!(
+		lines.nth(1),
+		Some(
+			"2023-01-01 12:01:40 UTC,0.1,DOT,,,,,0.425,USD,lost,polkadot slash in block 150,\
+			 0xefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefef"
+		)
+	);
+	assert_eq!(
+		lines.next(),
+		Some(
+			"2023-01-01 12:00:00 UTC,,,1.5,DOT,,,6.375,USD,staking,polkadot staking reward in block 100,\
+			 0xabababababababababababababababababababababababababababababababab"
+		)
+	);
+}
+
+#[test]
+fn koinly_books_every_reward_on_its_own_when_grouping() {
+	let dir = TempDir::new().unwrap();
+	let grouped = stdout(&run(&dir, &["-a", REWARDED, "-s", "--format", "koinly"]));
+	let dir = TempDir::new().unwrap();
+	let separated = stdout(&run(&dir, &["-a", REWARDED, "-s", "--format", "koinly", "--no-group"]));
+	assert_eq!(grouped, separated);
+	let hashes: Vec<_> = grouped.lines().skip(1).map(|l| l.rsplit(',').next().unwrap()).collect();
+	assert_eq!(hashes.len(), 3);
+	assert!(hashes.iter().all(|h| h.len() == 66 && h.starts_with("0x")), "{:?}", hashes);
+}
+
+#[test]
+fn rewards_outside_the_range_are_skipped() {
+	let dir = TempDir::new().unwrap();
+	let output = run(&dir, &["-a", REWARDED, "-s", "--no-price", "--from", "2023-01-02 00:00:00"]);
+	assert_eq!(stdout(&output), "date;block_nums;amount;slash;price\n2023-01-02;200;2.5;0;\n");
+}
+
+#[test]
+fn public_keys_are_encoded_for_the_network() {
+	let dir = TempDir::new().unwrap();
+	let by_key = run(&dir, &["-a", REWARDED_KEY, "-n", "polkadot", "-s"]);
+	let by_address = run(&dir, &["-a", REWARDED, "-s"]);
+	assert_eq!(stdout(&by_key), stdout(&by_address));
+}
+
+#[test]
+fn rewards_are_written_to_the_folder() {
+	let dir = TempDir::new().unwrap();
+	let output = run(&dir, &["-a", REWARDED, "-p", dir.path().to_str().unwrap()]);
+	assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+	let file = dir.path().join(format!("polkadot->usd-{REWARDED}-2023-01-01--2023-01-02-rewards.csv"));
+	let csv = fs::read_to_string(&file).unwrap_or_else(|e| panic!("{}: {e}", file.display()));
+	assert!(csv.starts_with("date;block_nums;amount;slash;price\n"));
+}
+
+#[test]
+fn addresses_file_writes_a_portfolio() {
+	let dir = TempDir::new().unwrap();
+	let accounts = dir.path().join("accounts.csv");
+	fs::write(&accounts, format!("address,network,label\n{REWARDED},polkadot,staking\n{NO_REWARDS},,empty\n")).unwrap();
+	let output = run(&dir, &["--addresses-file", accounts.to_str().unwrap(), "-p", dir.path().to_str().unwrap()]);
+	assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+	assert!(dir.path().join("portfolio->usd-accounts-rewards.csv").exists());
+	assert!(dir.path().join("portfolio-report->usd-accounts.csv").exists());
+}
+
+#[test]
+fn exit_codes_tell_errors_apart() {
+	let cases: &[(&[&str], i32)] = &[
+		(&["-a", "not-an-address"], 1),
+		(&["-a", NO_REWARDS], 2),
+		(&["-a", INVALID_KEY], 4),
+		(&["-a", REWARDED, "-c", "xyz"], 5),
+		(&["-a", NOT_RECORDED], 7),
+	];
+	for (args, code) in cases {
+		let dir = TempDir::new().unwrap();
+		let output = run(&dir, &[args, &["-s"][..]].concat());
+		assert_eq!(output.status.code(), Some(*code), "{args:?}: {}", String::from_utf8_lossy(&output.stderr));
+	}
+}
+
+/// Serve the fixtures in `dir` with the mock server, on a free port. Returns the server and its URL.
+fn mock(dir: &Path) -> (Child, String) {
+	let mut mock = Command::new(env!("CARGO_BIN_EXE_polkadot-rewards-mock"))
+		.arg(dir)
+		.args(["--listen", "127.0.0.1:0"])
+		.stdout(Stdio::piped())
+		.stderr(Stdio::null())
+		.spawn()
+		.expect("Failed to start the mock server");
+	let mut line = String::new();
+	BufReader::new(mock.stdout.take().unwrap()).read_line(&mut line).unwrap();
+	let url = line.split_whitespace().last().unwrap().to_string();
+	(mock, url)
+}
+
+#[test]
+fn mock_server_stands_in_for_subscan_and_coingecko() {
+	let (mut mock, url) = mock(&fixtures());
+	let dir = TempDir::new().unwrap();
+	let recorded = dir.path().join("recorded");
+	let subscan_url = format!("{url}/polkadot.api.subscan.io/api");
+	let price_url = format!("{url}/api.coingecko.com/api/v3");
+	let args = ["-a", REWARDED, "-s", "--subscan-url", &subscan_url, "--price-url", &price_url, "--price-rpm", "6000"];
+	let live = run_live(&dir, &[&args[..], &["--record", recorded.to_str().unwrap()]].concat());
+	mock.kill().unwrap();
+	mock.wait().unwrap();
+	assert_eq!(
+		stdout(&live),
+		"date;block_nums;amount;slash;price\n2023-01-02;200;2.5;0;4.5\n2023-01-01;100+150;1.5;0.1;4.25\n"
+	);
+
+	// the recording answers the same requests without the mock
+	let dir = TempDir::new().unwrap();
+	let replayed = run_live(&dir, &[&args[..], &["--replay", recorded.to_str().unwrap()]].concat());
+	assert_eq!(stdout(&replayed), stdout(&live));
+}
+
+#[test]
+fn mock_server_serves_every_network_under_its_own_host() {
+	let dir = TempDir::new().unwrap();
+	let served = dir.path().join("fixtures");
+	fs::create_dir(&served).unwrap();
+	for network in [fixtures(), fixtures().with_file_name("kusama")] {
+		for entry in fs::read_dir(network).unwrap() {
+			let path = entry.unwrap().path();
+			fs::copy(&path, served.join(path.file_name().unwrap())).unwrap();
+		}
+	}
+	let (mut mock, url) = mock(&served);
+
+	// the same key on both networks, each with rewards of its own
+	let accounts = dir.path().join("accounts.csv");
+	fs::write(&accounts, format!("address,network,label\n{REWARDED_KEY},polkadot,dot\n{REWARDED_KEY},kusama,ksm\n"))
+		.unwrap();
+	let subscan_url = format!("{url}/{{network}}.api.subscan.io/api");
+	let folder = dir.path().join("out");
+	fs::create_dir(&folder).unwrap();
+	let args = ["--addresses-file", accounts.to_str().unwrap(), "--subscan-url", &subscan_url, "--no-price"];
+	let output = run_live(&dir, &[&args[..], &["-p", folder.to_str().unwrap()]].concat());
+	mock.kill().unwrap();
+	mock.wait().unwrap();
+	assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+	let rewards = |network: &str| {
+		let path = fs::read_dir(&folder)
+			.unwrap()
+			.map(|entry| entry.unwrap().path())
+			.find(|path| path.file_name().unwrap().to_str().unwrap().starts_with(&format!("{network}->")))
+			.unwrap_or_else(|| panic!("No rewards of {}", network));
+		fs::read_to_string(path).unwrap()
+	};
+	assert_eq!(
+		rewards("polkadot"),
+		"date;block_nums;amount;slash;price\n2023-01-02;200;2.5;0;\n2023-01-01;100+150;1.5;0.1;\n"
+	);
+	assert_eq!(rewards("kusama"), "date;block_nums;amount;slash;price\n2023-01-01;300;0.5;0;\n");
+}